@@ -0,0 +1,56 @@
+use std::process::ExitCode;
+
+use futures_util::StreamExt;
+use orderbook::config::APP_CONFIG;
+use redis::AsyncCommands;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+/// Asks the running worker to recompute every balance from the ledger and
+/// compare it with the accounts it holds, and prints what it found. Exits
+/// non-zero if they disagree.
+#[tokio::main]
+async fn main() -> ExitCode {
+    match audit().await {
+        Ok(response) => report(&response),
+        Err(e) => {
+            eprintln!("❌ Audit failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Sends an `audit` request through the worker queue, the same way the server does.
+async fn audit() -> Result<Value, Box<dyn std::error::Error>> {
+    let client = redis::Client::open(APP_CONFIG.redis_url.as_str())?;
+    let request_id = Uuid::new_v4().to_string();
+
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(format!("order_response:{}", request_id)).await?;
+
+    let mut conn = client.get_async_connection().await?;
+    let request = json!({ "type": "audit", "request_id": request_id });
+    let _: () = conn.rpush("order", request.to_string()).await?;
+
+    let message = pubsub.on_message().next().await.ok_or("No response from the worker")?;
+    let payload: String = message.get_payload()?;
+    Ok(serde_json::from_str(&payload)?)
+}
+
+fn report(response: &Value) -> ExitCode {
+    if let Some(error) = response["error"].as_str() {
+        eprintln!("❌ {}", error);
+        return ExitCode::FAILURE;
+    }
+    let entries = response["entries"].as_u64().unwrap_or(0);
+    let problems = response["problems"].as_array().cloned().unwrap_or_default();
+    if problems.is_empty() {
+        println!("✅ {} ledger entries match the live balances", entries);
+        return ExitCode::SUCCESS;
+    }
+    for problem in &problems {
+        println!("❌ {}", problem.as_str().unwrap_or_default());
+    }
+    println!("{} problem(s) across {} ledger entries", problems.len(), entries);
+    ExitCode::FAILURE
+}
@@ -0,0 +1,162 @@
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    process::ExitCode,
+};
+
+use orderbook::{
+    engine::service::{capture_books, drain_book_events, drain_ledger_entries, replay_command, restore_books},
+    journal::Journal,
+    snapshot::Snapshot,
+};
+use serde_json::{Value, json};
+
+/// Replays a command journal through a fresh engine (books seeded from
+/// config.yaml, or from the snapshot the journal continues).
+///
+/// `replay record <journal> <output> [snapshot]` writes the trades and ledger
+/// entries of every command and the final books to `output`, one JSON document per line.
+/// `replay check <journal> <output> [snapshot]` replays again and reports the
+/// first place the result differs from a recorded `output`, so an engine change
+/// can be checked against the results of the build before it.
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let (Some(mode), Some(journal), Some(output)) = (args.get(1), args.get(2), args.get(3)) else {
+        eprintln!("Usage: replay <record|check> <journal> <output> [snapshot]");
+        return ExitCode::FAILURE;
+    };
+
+    let records = match replay(journal, args.get(4)) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("❌ Replay failed: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match mode.as_str() {
+        "record" => record(output, &records),
+        "check" => check(output, &records),
+        _ => Err(format!("Unknown mode {}, expected record or check", mode).into()),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// One record per journaled command, then one for the final books and accounts.
+fn replay(journal_path: &str, snapshot_path: Option<&String>) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let from = match snapshot_path {
+        Some(path) => {
+            let snapshot = Snapshot::read(path)?.ok_or(format!("No snapshot at {}", path))?;
+            let offset = snapshot.journal_offset;
+            restore_books(snapshot.books, snapshot.next_order_id, snapshot.accounts);
+            offset
+        }
+        None => 0,
+    };
+
+    let journal = Journal::read(journal_path)?;
+    if from < journal.base {
+        return Err(format!(
+            "Journal starts at offset {}; pass the snapshot it continues from",
+            journal.base
+        )
+        .into());
+    }
+
+    // Opening balances from config.yaml belong to no command
+    drain_ledger_entries();
+
+    let mut records = Vec::new();
+    for (offset, entry) in journal.entries.iter().filter(|(offset, _)| *offset >= from) {
+        let result = replay_command(&entry.command, &entry.clock());
+        drain_book_events();
+        records.push(json!({
+            "offset": offset,
+            "type": entry.command["type"].as_str().unwrap_or("order"),
+            "error": result.as_ref().err().map(|e| e.to_string()),
+            "trades": result.unwrap_or_default(),
+            "ledger": drain_ledger_entries()
+        }));
+    }
+
+    let (books, next_order_id, accounts) = capture_books();
+    records.push(json!({
+        "next_order_id": next_order_id,
+        "books": books,
+        "accounts": accounts
+    }));
+    Ok(records)
+}
+
+fn record(path: &str, records: &[Value]) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for record in records {
+        writeln!(out, "{}", record)?;
+    }
+    out.flush()?;
+    println!("📼 Recorded {} commands and the final books to {}", records.len() - 1, path);
+    Ok(true)
+}
+
+fn check(path: &str, records: &[Value]) -> Result<bool, Box<dyn std::error::Error>> {
+    let expected: Vec<Value> = BufReader::new(File::open(path)?)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+
+    for (i, (expected, actual)) in expected.iter().zip(records).enumerate() {
+        if let Some((at, want, got)) = first_difference(String::new(), expected, actual) {
+            let what = match actual["offset"].as_u64() {
+                Some(offset) => format!("command {} ({} at journal offset {})", i, actual["type"], offset),
+                None => "final books".to_string(),
+            };
+            println!("❌ First divergence in {}", what);
+            println!("   at {}: expected {}, got {}", if at.is_empty() { "." } else { &at }, want, got);
+            return Ok(false);
+        }
+    }
+    if expected.len() != records.len() {
+        println!(
+            "❌ Recorded {} commands but replayed {}",
+            expected.len().saturating_sub(1),
+            records.len() - 1
+        );
+        return Ok(false);
+    }
+
+    println!("✅ {} commands replayed; trades and final books match {}", records.len() - 1, path);
+    Ok(true)
+}
+
+/// Path and both values at the first place two JSON documents differ.
+fn first_difference(path: String, expected: &Value, actual: &Value) -> Option<(String, Value, Value)> {
+    match (expected, actual) {
+        (Value::Object(a), Value::Object(b)) => a
+            .keys()
+            .chain(b.keys().filter(|key| !a.contains_key(*key)))
+            .find_map(|key| {
+                first_difference(
+                    format!("{}.{}", path, key),
+                    a.get(key).unwrap_or(&Value::Null),
+                    b.get(key).unwrap_or(&Value::Null),
+                )
+            }),
+        (Value::Array(a), Value::Array(b)) => (0..a.len().max(b.len())).find_map(|i| {
+            first_difference(
+                format!("{}[{}]", path, i),
+                a.get(i).unwrap_or(&Value::Null),
+                b.get(i).unwrap_or(&Value::Null),
+            )
+        }),
+        _ if expected == actual => None,
+        _ => Some((path, expected.clone(), actual.clone())),
+    }
+}
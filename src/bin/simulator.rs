@@ -1,6 +1,4 @@
 
-mod websocket;
-
 use std::env;
 
 use orderbook::sim::runner::{run_multi_symbol_simulation, SimulatorConfig};
@@ -0,0 +1,76 @@
+use std::{env, process::ExitCode};
+
+use orderbook::{
+    config::APP_CONFIG,
+    snapshot::{SNAPSHOT_VERSION, Snapshot},
+};
+
+/// `snapshot inspect [path]` prints what a snapshot holds; `snapshot validate [path]`
+/// checks it and exits non-zero if recovery from it would be unsafe. The path
+/// defaults to `snapshot_path` from config.yaml.
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let command = args.get(1).map(String::as_str);
+    let path = args.get(2).cloned().unwrap_or_else(|| APP_CONFIG.snapshot_path.clone());
+
+    let snapshot = match Snapshot::read(&path) {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => {
+            eprintln!("No snapshot at {}", path);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("❌ {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match command {
+        Some("inspect") => {
+            inspect(&path, &snapshot);
+            ExitCode::SUCCESS
+        }
+        Some("validate") => {
+            let problems = snapshot.validate();
+            if problems.is_empty() {
+                println!("✅ {} is valid ({} books)", path, snapshot.books.len());
+                return ExitCode::SUCCESS;
+            }
+            for problem in &problems {
+                println!("❌ {}", problem);
+            }
+            println!("{} problem(s) in {}", problems.len(), path);
+            ExitCode::FAILURE
+        }
+        _ => {
+            eprintln!("Usage: snapshot <inspect|validate> [path]");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn inspect(path: &str, snapshot: &Snapshot) {
+    let taken_at = chrono::DateTime::from_timestamp(snapshot.taken_at, 0)
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_else(|| snapshot.taken_at.to_string());
+
+    println!("Snapshot:       {}", path);
+    println!("Version:        {}", SNAPSHOT_VERSION);
+    println!("Taken at:       {}", taken_at);
+    println!("Journal offset: {}", snapshot.journal_offset);
+    println!("Next order id:  {}", snapshot.next_order_id);
+    println!("Accounts:       {} users, {} orders holding funds", snapshot.accounts.balances.len(), snapshot.accounts.order_locks.len());
+
+    for book in &snapshot.books {
+        let spec = &book.instrument.spec;
+        let stops = book.buy_stops.values().chain(book.sell_stops.values()).map(|l| l.iter().count()).sum::<usize>();
+        println!();
+        println!("{} ({:?})", book.instrument.symbol, book.instrument.status);
+        println!("  orders:     {} resting, {} stops", book.order_index.len() - stops, stops);
+        println!("  levels:     {} bids, {} asks", book.bids.len(), book.asks.len());
+        println!("  best bid:   {}", book.current_best_bid.map(|p| spec.format_price(p)).unwrap_or("-".to_string()));
+        println!("  best ask:   {}", book.current_best_ask.map(|p| spec.format_price(p)).unwrap_or("-".to_string()));
+        println!("  last trade: {}", book.last_trade_price.map(|p| spec.format_price(p)).unwrap_or("-".to_string()));
+        println!("  next trade id: {}", book.next_trade_id);
+    }
+}
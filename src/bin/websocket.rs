@@ -1,53 +1,533 @@
-use std::env;
-use orderbook::{global::ORDERBOOKS, inputs::Symbol};
-use tokio::net::{TcpListener, TcpStream};
+use std::{collections::{HashMap, HashSet, VecDeque}, env, sync::Mutex, time::Duration};
+use orderbook::clock::NANOS_PER_SEC;
+use orderbook::inputs::{BookDepth, BookEventBatch, BookEventKind, Candle, CandleInterval, Decimal, DepthFeedMessage, DepthSnapshot, Instrument, InstrumentConfig, InstrumentSpec, LevelChange, Price, Quantity, Side, Symbol};
+use tokio::{net::{TcpListener, TcpStream}, sync::broadcast};
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 use futures_util::{SinkExt, StreamExt};
-use redis::Client;
-use serde_json::json;
+use redis::{AsyncCommands, Client};
+use serde_json::{json, Value};
 use chrono::Utc;
+use uuid::Uuid;
 
+/// Local copy of a worker book, kept current from the `depth_updates` feed.
+struct ReplicatedBook {
+    instrument: Instrument,
+    depth: BookDepth,
+    /// Sequence of the last diff sent to clients. Unlike `depth.seq` it never
+    /// goes backwards, even when the book is replaced by a worker snapshot.
+    seq: u64,
+}
 
-async fn handle_connection(stream: TcpStream) {
-    println!("New WebSocket connection");
-    
-    let ws_stream = match accept_async(stream).await {
-        Ok(ws) => ws,
-        Err(e) => {
-            eprintln!("WebSocket connection error: {}", e);
-            return;
+/// A `depth_update` client message, ready to send.
+#[derive(Clone)]
+struct DepthDiff {
+    symbol: Symbol,
+    seq: u64,
+    message: String,
+}
+
+/// An `l3_update` client message, ready to send.
+#[derive(Clone)]
+struct OrderEvents {
+    symbol: Symbol,
+    /// `seq` of the last event in the message
+    seq: u64,
+    message: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Channel {
+    Trades,
+    Ticker,
+    Candles(CandleInterval),
+}
+
+/// A `trade`, `ticker` or `candle` client message, ready to send.
+#[derive(Clone)]
+struct ChannelMessage {
+    channel: Channel,
+    symbol: Symbol,
+    message: String,
+}
+
+/// What one connection has subscribed to. On the book channel a symbol is
+/// followed either as L2 depth or as L3 order events, not both.
+#[derive(Default)]
+struct Subscriptions {
+    /// L2 symbols and the last depth sequence sent for each
+    depth: HashMap<Symbol, u64>,
+    /// L3 symbols and the last event sequence sent for each
+    orders: HashMap<Symbol, u64>,
+    trades: HashSet<Symbol>,
+    ticker: HashSet<Symbol>,
+    candles: HashSet<(Symbol, CandleInterval)>,
+}
+
+impl Subscriptions {
+    fn wants(&self, channel: Channel, symbol: &Symbol) -> bool {
+        match channel {
+            Channel::Trades => self.trades.contains(symbol),
+            Channel::Ticker => self.ticker.contains(symbol),
+            Channel::Candles(interval) => self.candles.contains(&(symbol.clone(), interval)),
+        }
+    }
+}
+
+const TICKER_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// Trading in one symbol over the last 24 hours. At startup it is seeded with
+/// the worker's persisted 1m candles, so a restart keeps the statistics;
+/// trades relayed after that are added one by one.
+#[derive(Default)]
+struct TickerStats {
+    /// One entry per relayed trade or seeded bar, oldest first
+    entries: VecDeque<TickerEntry>,
+    volume: Quantity,
+    last_price: Option<Price>,
+}
+
+/// A trade, or a 1m bar standing in for the trades it aggregates.
+struct TickerEntry {
+    /// Unix seconds of the trade or the bar's open
+    timestamp: i64,
+    open: Price,
+    high: Price,
+    low: Price,
+    volume: Quantity,
+}
+
+impl TickerStats {
+    fn record(&mut self, timestamp: i64, price: Price, qty: Quantity) {
+        self.push(TickerEntry { timestamp, open: price, high: price, low: price, volume: qty }, price);
+        self.expire(timestamp);
+    }
+
+    fn seed(&mut self, bar: &Candle) {
+        self.push(
+            TickerEntry { timestamp: bar.open_time, open: bar.open, high: bar.high, low: bar.low, volume: bar.volume },
+            bar.close,
+        );
+    }
+
+    fn push(&mut self, entry: TickerEntry, close: Price) {
+        self.volume += entry.volume;
+        self.entries.push_back(entry);
+        self.last_price = Some(close);
+    }
+
+    fn expire(&mut self, now: i64) {
+        while let Some(entry) = self.entries.front()
+            && entry.timestamp <= now - TICKER_WINDOW_SECS
+        {
+            self.volume -= entry.volume;
+            self.entries.pop_front();
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BOOKS: Mutex<HashMap<Symbol, ReplicatedBook>> = Mutex::new(HashMap::new());
+    /// Diffs are sent while `BOOKS` is locked, so a snapshot taken under the same
+    /// lock is exactly the state before every diff still queued for a receiver.
+    static ref DEPTH_DIFFS: broadcast::Sender<DepthDiff> = broadcast::channel(1024).0;
+    static ref ORDER_EVENTS: broadcast::Sender<OrderEvents> = broadcast::channel(1024).0;
+    static ref TICKERS: Mutex<HashMap<Symbol, TickerStats>> = Mutex::new(HashMap::new());
+    static ref CHANNEL_MESSAGES: broadcast::Sender<ChannelMessage> = broadcast::channel(1024).0;
+}
+
+/// Turns the worker's `market_updates` into `trade` messages, one per execution,
+/// and keeps the 24h ticker statistics.
+async fn relay_trades(redis_client: Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe("market_updates").await?;
+
+    let mut on_message = pubsub.on_message();
+    while let Some(msg) = on_message.next().await {
+        let update = match msg.get_payload::<String>().map(|payload| serde_json::from_str::<Value>(&payload)) {
+            Ok(Ok(update)) => update,
+            Ok(Err(e)) => {
+                eprintln!("Invalid market update: {}", e);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error reading market update: {}", e);
+                continue;
+            }
+        };
+        let Some(symbol) = update["symbol"].as_str().and_then(lookup_symbol) else {
+            continue;
+        };
+        let timestamp = update["timestamp"].as_i64().unwrap_or_else(|| Utc::now().timestamp());
+
+        let books = BOOKS.lock().unwrap();
+        let Some(book) = books.get(&symbol) else {
+            continue;
+        };
+        let spec = &book.instrument.spec;
+        let mut tickers = TICKERS.lock().unwrap();
+        let stats = tickers.entry(symbol.clone()).or_default();
+
+        for trade in update["trades"].as_array().into_iter().flatten() {
+            let price = trade["price"].as_str().and_then(|p| Decimal::from(p).to_units(spec.price_decimals));
+            let qty = trade["quantity"].as_str().and_then(|q| Decimal::from(q).to_units(spec.quantity_decimals));
+            // Trades are stamped in unix nanoseconds when they matched, which
+            // may be well before the update was published
+            let traded_at = trade["timestamp"].as_i64().map_or(timestamp, |nanos| nanos.div_euclid(NANOS_PER_SEC));
+            if let (Some(price), Some(qty)) = (price, qty) {
+                stats.record(traded_at, Price(price), Quantity(qty));
+            }
+
+            let message = json!({
+                "type": "trade",
+                "symbol": symbol,
+                "trade_id": trade["trade_id"],
+                "price": trade["price"],
+                "quantity": trade["quantity"],
+                "side": trade["side"],
+                "timestamp": trade["timestamp"]
+            });
+            let _ = CHANNEL_MESSAGES.send(ChannelMessage {
+                channel: Channel::Trades,
+                symbol: symbol.clone(),
+                message: message.to_string(),
+            });
+        }
+
+        let _ = CHANNEL_MESSAGES.send(ChannelMessage {
+            channel: Channel::Ticker,
+            symbol: symbol.clone(),
+            message: ticker_json(book, stats, timestamp).to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Seeds the 24h ticker statistics of every instrument from the 1m candles the
+/// worker persists: the closed bars under `candles:{symbol}:1m` and the bar in
+/// progress under `candles:{symbol}:1m:open`.
+async fn seed_tickers(redis_client: &Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = request_worker(redis_client, json!({ "type": "list_instruments" })).await?;
+    let instruments: Vec<InstrumentConfig> = serde_json::from_value(response["instruments"].clone())?;
+    let mut conn = redis_client.get_async_connection().await?;
+    let since = Utc::now().timestamp() - TICKER_WINDOW_SECS;
+
+    for instrument in instruments {
+        let key = format!("candles:{}:1m", instrument.symbol);
+        let stored: Vec<String> = conn.zrangebyscore(&key, since + 1, "+inf").await?;
+        let mut bars: Vec<Candle> = stored.iter().filter_map(|c| serde_json::from_str(c).ok()).collect();
+        let open: Option<String> = conn.get(format!("{}:open", key)).await?;
+        if let Some(open) = open.and_then(|c| serde_json::from_str::<Candle>(&c).ok())
+            && open.open_time > since
+            && bars.last().is_none_or(|last| last.open_time < open.open_time)
+        {
+            bars.push(open);
+        }
+
+        let mut tickers = TICKERS.lock().unwrap();
+        let stats = tickers.entry(instrument.symbol).or_default();
+        for bar in &bars {
+            stats.seed(bar);
         }
+    }
+    Ok(())
+}
+
+/// Relays the worker's bar updates, both in-progress and closed, as `candle` messages.
+async fn relay_candles(redis_client: Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe("candles").await?;
+
+    let mut on_message = pubsub.on_message();
+    while let Some(msg) = on_message.next().await {
+        let mut candle = match msg.get_payload::<String>().map(|payload| serde_json::from_str::<Value>(&payload)) {
+            Ok(Ok(candle)) => candle,
+            Ok(Err(e)) => {
+                eprintln!("Invalid candle: {}", e);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error reading candle: {}", e);
+                continue;
+            }
+        };
+        let Some(symbol) = candle["symbol"].as_str().and_then(lookup_symbol) else {
+            continue;
+        };
+        let Ok(interval) = serde_json::from_value::<CandleInterval>(candle["interval"].clone()) else {
+            continue;
+        };
+
+        candle["type"] = json!("candle");
+        let _ = CHANNEL_MESSAGES.send(ChannelMessage {
+            channel: Channel::Candles(interval),
+            symbol,
+            message: candle.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Current ticker for a book: top of book plus 24h trade statistics.
+fn ticker_json(book: &ReplicatedBook, stats: &mut TickerStats, now: i64) -> Value {
+    stats.expire(now);
+    let spec = &book.instrument.spec;
+    let open = stats.entries.front().map(|entry| entry.open);
+    let high = stats.entries.iter().map(|entry| entry.high).max();
+    let low = stats.entries.iter().map(|entry| entry.low).min();
+    let change = match (open, stats.last_price) {
+        (Some(open), Some(last)) if last >= open => Some(spec.format_price(last - open)),
+        (Some(open), Some(last)) => Some(format!("-{}", spec.format_price(open - last))),
+        _ => None,
+    };
+    let change_percent = match (open, stats.last_price) {
+        (Some(open), Some(last)) => Some(format!("{:.2}", (last.0 as f64 - open.0 as f64) / open.0 as f64 * 100.0)),
+        _ => None,
     };
 
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-    let mut subscriptions: Vec<Symbol> = Vec::new();
-    
-    let redis_client = match Client::open("redis://127.0.0.1:6379/".to_string()) {
-        Ok(client) => client,
+    json!({
+        "type": "ticker",
+        "symbol": book.instrument.symbol,
+        "best_bid": book.depth.bids.keys().next_back().map(|p| spec.format_price(*p)),
+        "best_ask": book.depth.asks.keys().next().map(|p| spec.format_price(*p)),
+        "last_price": stats.last_price.or(book.depth.last_trade_price).map(|p| spec.format_price(p)),
+        "volume_24h": spec.format_quantity(stats.volume),
+        "high_24h": high.map(|p| spec.format_price(p)),
+        "low_24h": low.map(|p| spec.format_price(p)),
+        "open_24h": open.map(|p| spec.format_price(p)),
+        "change_24h": change,
+        "change_percent_24h": change_percent,
+        "timestamp": now
+    })
+}
+
+fn get_ticker(symbol: &Symbol) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let books = BOOKS.lock().unwrap();
+    let book = books.get(symbol).ok_or("Symbol not found")?;
+    let mut tickers = TICKERS.lock().unwrap();
+    Ok(ticker_json(book, tickers.entry(symbol.clone()).or_default(), Utc::now().timestamp()))
+}
+
+/// Relays the worker's per-symbol L3 channels to connected clients.
+async fn relay_book_events(redis_client: Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.psubscribe("book_events:*").await?;
+
+    let mut on_message = pubsub.on_message();
+    while let Some(msg) = on_message.next().await {
+        let batch = match msg.get_payload::<String>().map(|payload| serde_json::from_str::<BookEventBatch>(&payload)) {
+            Ok(Ok(batch)) => batch,
+            Ok(Err(e)) => {
+                eprintln!("Invalid book events: {}", e);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error reading book events: {}", e);
+                continue;
+            }
+        };
+
+        let books = BOOKS.lock().unwrap();
+        let Some(book) = books.get(&batch.symbol) else {
+            continue;
+        };
+        let spec = &book.instrument.spec;
+        let message = json!({
+            "type": "l3_update",
+            "symbol": batch.symbol,
+            "events": batch.events.iter().map(|event| json!({
+                "seq": event.seq,
+                "event": match event.kind {
+                    BookEventKind::Add => "add",
+                    BookEventKind::Modify => "modify",
+                    BookEventKind::Cancel => "cancel",
+                    BookEventKind::Execute => "execute",
+                },
+                "order_id": event.order_id,
+                "side": match event.side { Side::Buy => "buy", Side::Sell => "sell" },
+                "price": spec.format_price(event.price),
+                "quantity": spec.format_quantity(event.qty),
+                "trade_id": event.trade_id
+            })).collect::<Vec<_>>()
+        });
+        let seq = batch.events.last().map(|event| event.seq).unwrap_or_default();
+        let _ = ORDER_EVENTS.send(OrderEvents { symbol: batch.symbol, seq, message: message.to_string() });
+    }
+    Ok(())
+}
+
+/// Follows the worker's depth feed. Snapshots replace a book outright; an update
+/// that does not follow on from the local `seq` triggers a fresh snapshot.
+async fn replicate_depth(redis_client: Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe("depth_updates").await?;
+
+    // Subscribed first so nothing published after the snapshot is missed
+    load_snapshots(&redis_client, None).await;
+
+    let mut on_message = pubsub.on_message();
+    while let Some(msg) = on_message.next().await {
+        let payload: String = match msg.get_payload() {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("Error reading depth update: {}", e);
+                continue;
+            }
+        };
+        let update = match serde_json::from_str::<DepthFeedMessage>(&payload) {
+            Ok(DepthFeedMessage::Snapshot(snapshot)) => {
+                apply_snapshot(*snapshot);
+                continue;
+            }
+            Ok(DepthFeedMessage::Update(update)) => update,
+            Err(e) => {
+                eprintln!("Invalid depth update: {}", e);
+                continue;
+            }
+        };
+
+        let in_sync = {
+            let mut books = BOOKS.lock().unwrap();
+            match books.get_mut(&update.symbol) {
+                Some(book) if update.seq <= book.depth.seq => true,
+                Some(book) if update.seq == book.depth.seq + 1 => {
+                    book.depth.apply(&update);
+                    publish_diff(book, &update.changes);
+                    true
+                }
+                _ => false,
+            }
+        };
+        if !in_sync {
+            println!("Depth gap on {}, requesting snapshot", update.symbol);
+            load_snapshots(&redis_client, Some(&update.symbol)).await;
+        }
+    }
+    Ok(())
+}
+
+async fn load_snapshots(redis_client: &Client, symbol: Option<&Symbol>) {
+    let request = json!({ "type": "depth_snapshot", "symbol": symbol });
+    let books = match request_worker(redis_client, request).await {
+        Ok(response) => serde_json::from_value::<Vec<DepthSnapshot>>(response["books"].clone()),
         Err(e) => {
-            eprintln!("Redis connection error: {}", e);
+            eprintln!("Failed to fetch depth snapshot: {}", e);
             return;
         }
     };
+    match books {
+        Ok(books) => books.into_iter().for_each(apply_snapshot),
+        Err(e) => eprintln!("Invalid depth snapshot: {}", e),
+    }
+}
 
-    let pubsub_conn = match redis_client.get_async_connection().await {
-        Ok(conn) => conn,
+fn apply_snapshot(snapshot: DepthSnapshot) {
+    let instrument = match Instrument::from_config(&snapshot.instrument) {
+        Ok(instrument) => instrument,
         Err(e) => {
-            eprintln!("Redis pubsub connection error: {}", e);
+            eprintln!("Invalid instrument in depth snapshot: {}", e);
             return;
         }
     };
+    let mut books = BOOKS.lock().unwrap();
+    match books.get_mut(&instrument.symbol) {
+        Some(book) => {
+            // Clients keep their sequence; they see the jump as an ordinary diff
+            let changes = book.depth.changes_to(&snapshot.depth);
+            book.instrument = instrument;
+            book.depth = snapshot.depth;
+            publish_diff(book, &changes);
+        }
+        None => {
+            books.insert(instrument.symbol.clone(), ReplicatedBook { instrument, depth: snapshot.depth, seq: 0 });
+        }
+    }
+}
+
+/// Whether a change (already applied to `depth`) touched the best bid or ask.
+fn is_top_of_book(depth: &BookDepth, change: &LevelChange) -> bool {
+    // With the side now empty, the change must have removed its last level
+    match change.side {
+        Side::Buy => depth.bids.keys().next_back().is_none_or(|best| change.price >= *best),
+        Side::Sell => depth.asks.keys().next().is_none_or(|best| change.price <= *best),
+    }
+}
 
-    let mut pubsub = pubsub_conn.into_pubsub();
-    if let Err(e) = pubsub.subscribe("market_updates").await {
-        eprintln!("Failed to subscribe to market updates: {}", e);
+/// Sends changed levels to subscribed clients under the book's next sequence number.
+fn publish_diff(book: &mut ReplicatedBook, changes: &[LevelChange]) {
+    if changes.is_empty() {
         return;
     }
+    book.seq += 1;
+    let spec = &book.instrument.spec;
+    if changes.iter().any(|change| is_top_of_book(&book.depth, change)) {
+        let mut tickers = TICKERS.lock().unwrap();
+        let stats = tickers.entry(book.instrument.symbol.clone()).or_default();
+        let _ = CHANNEL_MESSAGES.send(ChannelMessage {
+            channel: Channel::Ticker,
+            symbol: book.instrument.symbol.clone(),
+            message: ticker_json(book, stats, Utc::now().timestamp()).to_string(),
+        });
+    }
+    let message = json!({
+        "type": "depth_update",
+        "symbol": book.instrument.symbol,
+        "seq": book.seq,
+        "changes": changes.iter().map(|change| json!({
+            "side": match change.side { Side::Buy => "buy", Side::Sell => "sell" },
+            "price": spec.format_price(change.price),
+            "quantity": spec.format_quantity(change.quantity)
+        })).collect::<Vec<_>>()
+    });
+    // No receivers just means no clients are connected
+    let _ = DEPTH_DIFFS.send(DepthDiff {
+        symbol: book.instrument.symbol.clone(),
+        seq: book.seq,
+        message: message.to_string(),
+    });
+}
+
+/// Sends a request through the worker queue and waits for its reply.
+async fn request_worker(redis_client: &Client, mut request: Value) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let request_id = Uuid::new_v4().to_string();
+    request["request_id"] = Value::String(request_id.clone());
+
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(format!("order_response:{}", request_id)).await?;
+
+    let mut conn = redis_client.get_async_connection().await?;
+    let _: () = conn.rpush("order", request.to_string()).await?;
+
+    let mut on_message = pubsub.on_message();
+    let msg = tokio::time::timeout(Duration::from_secs(5), on_message.next())
+        .await?
+        .ok_or("Response channel closed")?;
+    let payload: String = msg.get_payload()?;
+    Ok(serde_json::from_str(&payload)?)
+}
+
+
+async fn handle_connection(stream: TcpStream, redis_client: Client) {
+    println!("New WebSocket connection");
+    
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("WebSocket connection error: {}", e);
+            return;
+        }
+    };
 
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let mut subscriptions = Subscriptions::default();
+    let mut depth_diffs = DEPTH_DIFFS.subscribe();
+    let mut order_events = ORDER_EVENTS.subscribe();
+    let mut channel_messages = CHANNEL_MESSAGES.subscribe();
+
+    
     let welcome_msg = json!({
         "type": "welcome",
         "message": "Connected to OrderBook WebSocket",
-        "available_symbols": ["BTCUSD", "ETHUSD", "SOLUSD"]
+        "available_symbols": available_symbols()
     });
     
     if let Err(e) = ws_sender.send(Message::Text(welcome_msg.to_string())).await {
@@ -55,14 +535,13 @@ async fn handle_connection(stream: TcpStream) {
         return;
     }
 
-    let mut on_message = pubsub.on_message();
     loop {
         tokio::select! {
             // Handle incoming WebSocket messages
             msg = ws_receiver.next() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
-                        if let Err(e) = handle_client_message(&text, &mut ws_sender, &mut subscriptions).await {
+                        if let Err(e) = handle_client_message(&text, &mut ws_sender, &mut subscriptions, &redis_client).await {
                             eprintln!("Error handling client message: {}", e);
                             break;
                         }
@@ -80,36 +559,62 @@ async fn handle_connection(stream: TcpStream) {
                 }
             }
             
-            update = on_message.next() => {
-                if let Some(msg) = update {
-                    println!("{:?}",msg);
-                    if let Ok(payload) = msg.get_payload::<String>() {
-                        if let Ok(update_data) = serde_json::from_str::<serde_json::Value>(&payload) {
-                            if let Some(symbol_str) = update_data["symbol"].as_str() {
-                                let symbol = match symbol_str {
-                                    "BTCUSD" => Symbol::BTCUSD,
-                                    "ETHUSD" => Symbol::ETHUSD, 
-                                    "SOLUSD" => Symbol::SOLUSD,
-                                    _ => continue,
-                                };
-                                
-                                if subscriptions.contains(&symbol) {
-                                    if let Ok(orderbook_data) = get_orderbook_with_trades(&symbol, &update_data) {
-                                        let market_update = json!({
-                                            "type": "orderbook_update",
-                                            "symbol": symbol_str,
-                                            "data": orderbook_data
-                                        });
-                                        
-                                        if let Err(e) = ws_sender.send(Message::Text(market_update.to_string())).await {
-                                            eprintln!("Failed to send market update: {}", e);
-                                            break;
-                                        }
-                                    }
-                                }
+            message = channel_messages.recv() => {
+                match message {
+                    Ok(message) => {
+                        if subscriptions.wants(message.channel, &message.symbol)
+                            && let Err(e) = ws_sender.send(Message::Text(message.message)).await
+                        {
+                            eprintln!("Failed to send market update: {}", e);
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection fell behind, skipped {} trade/ticker/candle updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            diff = depth_diffs.recv() => {
+                match diff {
+                    Ok(diff) => {
+                        if let Some(last_seq) = subscriptions.depth.get_mut(&diff.symbol)
+                            && diff.seq > *last_seq
+                        {
+                            *last_seq = diff.seq;
+                            if let Err(e) = ws_sender.send(Message::Text(diff.message)).await {
+                                eprintln!("Failed to send depth update: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    // The client sees the dropped diffs as a sequence gap and resyncs
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection fell behind, skipped {} depth updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            events = order_events.recv() => {
+                match events {
+                    Ok(events) => {
+                        if let Some(last_seq) = subscriptions.orders.get_mut(&events.symbol)
+                            && events.seq > *last_seq
+                        {
+                            *last_seq = events.seq;
+                            if let Err(e) = ws_sender.send(Message::Text(events.message)).await {
+                                eprintln!("Failed to send L3 update: {}", e);
+                                break;
                             }
                         }
                     }
+                    // Event `seq` numbers let the client see what was dropped
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection fell behind, skipped {} L3 updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
                 }
             }
         }
@@ -121,53 +626,195 @@ async fn handle_connection(stream: TcpStream) {
 async fn handle_client_message(
     text: &str, 
     ws_sender: &mut futures_util::stream::SplitSink<tokio_tungstenite::WebSocketStream<TcpStream>, Message>,
-    subscriptions: &mut Vec<Symbol>
+    subscriptions: &mut Subscriptions,
+    redis_client: &Client
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let msg: serde_json::Value = serde_json::from_str(text)?;
     
     match msg["type"].as_str() {
         Some("subscribe") => {
             let symbol_str = msg["symbol"].as_str().unwrap_or("BTCUSD");
-            let symbol = match symbol_str {
-                "BTCUSD" => Symbol::BTCUSD,
-                "ETHUSD" => Symbol::ETHUSD,
-                "SOLUSD" => Symbol::SOLUSD,
+            let Some(symbol) = lookup_symbol(symbol_str) else {
+                let error = json!({
+                    "type": "error",
+                    "message": format!("Invalid symbol. Available: {}", available_symbols().join(", "))
+                });
+                ws_sender.send(Message::Text(error.to_string())).await?;
+                return Ok(());
+            };
+
+            let channel = msg["channel"].as_str().unwrap_or("book");
+            match (channel, msg["level"].as_str().unwrap_or("L2")) {
+                ("book", "L2") => {
+                    let (seq, depth) = get_depth_snapshot(&symbol)?;
+                    subscriptions.orders.remove(&symbol);
+                    subscriptions.depth.insert(symbol, seq);
+
+                    let response = json!({
+                        "type": "subscription_confirmed",
+                        "channel": "book",
+                        "symbol": symbol_str,
+                        "level": "L2",
+                        "message": format!("Subscribed to {} orderbook", symbol_str),
+                        "seq": seq,
+                        "orderbook": depth
+                    });
+                    ws_sender.send(Message::Text(response.to_string())).await?;
+                }
+                ("book", "L3") => {
+                    let (seq, orders) = match get_l3_snapshot(redis_client, &symbol).await {
+                        Ok(snapshot) => snapshot,
+                        Err(e) => {
+                            let error = json!({ "type": "error", "message": format!("L3 snapshot unavailable: {}", e) });
+                            ws_sender.send(Message::Text(error.to_string())).await?;
+                            return Ok(());
+                        }
+                    };
+                    subscriptions.depth.remove(&symbol);
+                    subscriptions.orders.insert(symbol, seq);
+
+                    let response = json!({
+                        "type": "subscription_confirmed",
+                        "channel": "book",
+                        "symbol": symbol_str,
+                        "level": "L3",
+                        "message": format!("Subscribed to {} order events", symbol_str),
+                        "seq": seq,
+                        "orders": orders
+                    });
+                    ws_sender.send(Message::Text(response.to_string())).await?;
+                }
+                ("trades", _) => {
+                    subscriptions.trades.insert(symbol);
+
+                    let response = json!({
+                        "type": "subscription_confirmed",
+                        "channel": "trades",
+                        "symbol": symbol_str,
+                        "message": format!("Subscribed to {} trades", symbol_str)
+                    });
+                    ws_sender.send(Message::Text(response.to_string())).await?;
+                }
+                ("ticker", _) => {
+                    let ticker = get_ticker(&symbol)?;
+                    subscriptions.ticker.insert(symbol);
+
+                    let response = json!({
+                        "type": "subscription_confirmed",
+                        "channel": "ticker",
+                        "symbol": symbol_str,
+                        "message": format!("Subscribed to {} ticker", symbol_str),
+                        "ticker": ticker
+                    });
+                    ws_sender.send(Message::Text(response.to_string())).await?;
+                }
+                ("candles", _) => {
+                    let Ok(interval) = serde_json::from_value::<CandleInterval>(msg["interval"].clone()) else {
+                        let error = json!({
+                            "type": "error",
+                            "message": "Invalid interval. Available: 1s, 1m, 5m, 15m, 1h, 1d"
+                        });
+                        ws_sender.send(Message::Text(error.to_string())).await?;
+                        return Ok(());
+                    };
+                    subscriptions.candles.insert((symbol, interval));
+
+                    let response = json!({
+                        "type": "subscription_confirmed",
+                        "channel": "candles",
+                        "symbol": symbol_str,
+                        "interval": interval,
+                        "message": format!("Subscribed to {} {} candles", symbol_str, interval.as_str())
+                    });
+                    ws_sender.send(Message::Text(response.to_string())).await?;
+                }
                 _ => {
                     let error = json!({
                         "type": "error",
-                        "message": "Invalid symbol. Available: BTCUSD, ETHUSD, SOLUSD"
+                        "message": "Invalid channel. Available: book (level L2 or L3), trades, ticker, candles"
                     });
                     ws_sender.send(Message::Text(error.to_string())).await?;
-                    return Ok(());
                 }
+            }
+        }
+        Some("resync") => {
+            let symbol_str = msg["symbol"].as_str().unwrap_or("BTCUSD");
+            let Some(symbol) = lookup_symbol(symbol_str) else {
+                let error = json!({
+                    "type": "error",
+                    "message": "Invalid symbol"
+                });
+                ws_sender.send(Message::Text(error.to_string())).await?;
+                return Ok(());
             };
 
-            if !subscriptions.contains(&symbol) {
-                subscriptions.push(symbol.clone());
+            if msg["level"].as_str() == Some("L3") {
+                // Events already queued at or below the snapshot's sequence are skipped
+                let (seq, orders) = match get_l3_snapshot(redis_client, &symbol).await {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => {
+                        let error = json!({ "type": "error", "message": format!("L3 snapshot unavailable: {}", e) });
+                        ws_sender.send(Message::Text(error.to_string())).await?;
+                        return Ok(());
+                    }
+                };
+                subscriptions.depth.remove(&symbol);
+                subscriptions.orders.insert(symbol, seq);
+
+                let response = json!({
+                    "type": "l3_snapshot",
+                    "symbol": symbol_str,
+                    "seq": seq,
+                    "orders": orders
+                });
+                ws_sender.send(Message::Text(response.to_string())).await?;
+                return Ok(());
             }
-            
-            let orderbook_snapshot = get_full_orderbook_snapshot(&symbol)?;
+
+            // Diffs already queued at or below the new sequence are skipped
+            let (seq, depth) = get_depth_snapshot(&symbol)?;
+            subscriptions.orders.remove(&symbol);
+            subscriptions.depth.insert(symbol, seq);
+
             let response = json!({
-                "type": "subscription_confirmed",
+                "type": "depth_snapshot",
                 "symbol": symbol_str,
-                "message": format!("Subscribed to {} orderbook", symbol_str),
-                "orderbook": orderbook_snapshot
+                "seq": seq,
+                "orderbook": depth
             });
             ws_sender.send(Message::Text(response.to_string())).await?;
         }
         Some("unsubscribe") => {
             let symbol_str = msg["symbol"].as_str().unwrap_or("BTCUSD");
-            let symbol = match symbol_str {
-                "BTCUSD" => Symbol::BTCUSD,
-                "ETHUSD" => Symbol::ETHUSD,
-                "SOLUSD" => Symbol::SOLUSD,
-                _ => return Ok(()),
+            let Some(symbol) = lookup_symbol(symbol_str) else {
+                return Ok(());
             };
             
-            subscriptions.retain(|s| s != &symbol);
+            let channel = msg["channel"].as_str().unwrap_or("book");
+            match channel {
+                "book" => {
+                    subscriptions.depth.remove(&symbol);
+                    subscriptions.orders.remove(&symbol);
+                }
+                "trades" => {
+                    subscriptions.trades.remove(&symbol);
+                }
+                "ticker" => {
+                    subscriptions.ticker.remove(&symbol);
+                }
+                // Without an interval every candle subscription for the symbol goes
+                "candles" => match serde_json::from_value::<CandleInterval>(msg["interval"].clone()) {
+                    Ok(interval) => {
+                        subscriptions.candles.remove(&(symbol, interval));
+                    }
+                    Err(_) => subscriptions.candles.retain(|(s, _)| *s != symbol),
+                },
+                _ => return Ok(()),
+            }
             
             let response = json!({
                 "type": "unsubscribe_confirmed",
+                "channel": channel,
                 "symbol": symbol_str,
                 "message": format!("Unsubscribed from {}", symbol_str)
             });
@@ -175,18 +822,13 @@ async fn handle_client_message(
         }
         Some("get_orderbook") => {
             let symbol_str = msg["symbol"].as_str().unwrap_or("BTCUSD");
-            let symbol = match symbol_str {
-                "BTCUSD" => Symbol::BTCUSD,
-                "ETHUSD" => Symbol::ETHUSD,
-                "SOLUSD" => Symbol::SOLUSD,
-                _ => {
-                    let error = json!({
-                        "type": "error",
-                        "message": "Invalid symbol"
-                    });
-                    ws_sender.send(Message::Text(error.to_string())).await?;
-                    return Ok(());
-                }
+            let Some(symbol) = lookup_symbol(symbol_str) else {
+                let error = json!({
+                    "type": "error",
+                    "message": "Invalid symbol"
+                });
+                ws_sender.send(Message::Text(error.to_string())).await?;
+                return Ok(());
             };
             
             let orderbook_snapshot = get_full_orderbook_snapshot(&symbol)?;
@@ -199,20 +841,16 @@ async fn handle_client_message(
         }
         Some("price") =>{
             let symbol_str= msg["symbol"].as_str().unwrap_or("BTCUSD");
-             let symbol = match symbol_str {
-                "BTCUSD" => Symbol::BTCUSD,
-                "ETHUSD" => Symbol::ETHUSD,
-                "SOLUSD" => Symbol::SOLUSD,
-                _ => {
-                    let error = json!({
-                        "type": "error",
-                        "message": "Invalid symbol"
-                    });
-                    ws_sender.send(Message::Text(error.to_string())).await?;
-                    return Ok(());
-                }
+            let Some(symbol) = lookup_symbol(symbol_str) else {
+                let error = json!({
+                    "type": "error",
+                    "message": "Invalid symbol"
+                });
+                ws_sender.send(Message::Text(error.to_string())).await?;
+                return Ok(());
             };
-            ws_sender.send(Message::Binary("BTCUSD".into()))   .await?;
+            let ticker = get_ticker(&symbol)?;
+            ws_sender.send(Message::Text(ticker.to_string())).await?;
         }
         Some("ping") => {
             let pong = json!({
@@ -224,7 +862,7 @@ async fn handle_client_message(
         _ => {
             let error = json!({
                 "type": "error",
-                "message": "Unknown message type. Available: subscribe, unsubscribe, get_orderbook, ping"
+                "message": "Unknown message type. Available: subscribe, unsubscribe, resync, get_orderbook, price, ping"
             });
             ws_sender.send(Message::Text(error.to_string())).await?;
         }
@@ -233,27 +871,41 @@ async fn handle_client_message(
     Ok(())
 }
 
+/// Resolves a client-supplied symbol against the replicated books.
+fn lookup_symbol(symbol_str: &str) -> Option<Symbol> {
+    let symbol = Symbol::from(symbol_str);
+    BOOKS.lock().unwrap().contains_key(&symbol).then_some(symbol)
+}
+
+fn available_symbols() -> Vec<String> {
+    let mut symbols: Vec<String> = BOOKS.lock().unwrap().keys().map(|s| s.to_string()).collect();
+    symbols.sort();
+    symbols
+}
+
 fn get_full_orderbook_snapshot(symbol: &Symbol) -> Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>> {
-    let orderbooks = ORDERBOOKS.lock().unwrap();
-    let orderbook = orderbooks.get(symbol).ok_or("Symbol not found")?;
+    let books = BOOKS.lock().unwrap();
+    let book = books.get(symbol).ok_or("Symbol not found")?;
     
-    let depth = orderbook.get_depth(10);
+    let depth = book.depth.top(10);
+    let spec = &book.instrument.spec;
     
     let orderbook_data = json!({
-        "symbol": format!("{:?}", symbol),
-        "current_price": orderbook.current_price.map(|p| p as f64 / 100.0),
-        "last_trade_price": orderbook.last_trade_price.map(|p| p as f64 / 100.0),
-        "best_bid": orderbook.current_best_bid.map(|p| p as f64 / 100.0),
-        "best_ask": orderbook.current_best_ask.map(|p| p as f64 / 100.0),
+        "symbol": symbol,
+        "seq": book.seq,
+        "current_price": book.depth.last_trade_price.map(|p| spec.format_price(p)),
+        "last_trade_price": book.depth.last_trade_price.map(|p| spec.format_price(p)),
+        "best_bid": book.depth.bids.keys().next_back().map(|p| spec.format_price(*p)),
+        "best_ask": book.depth.asks.keys().next().map(|p| spec.format_price(*p)),
         "bids": depth.bids.iter().map(|(price, qty)| json!({
-            "price": *price as f64 / 100.0,
-            "quantity": qty,
-            "total": (*price as f64 / 100.0) * (*qty as f64)
+            "price": spec.format_price(*price),
+            "quantity": spec.format_quantity(*qty),
+            "total": spec.format_notional(*price, *qty)
         })).collect::<Vec<_>>(),
         "asks": depth.asks.iter().map(|(price, qty)| json!({
-            "price": *price as f64 / 100.0,
-            "quantity": qty,
-            "total": (*price as f64 / 100.0) * (*qty as f64)
+            "price": spec.format_price(*price),
+            "quantity": spec.format_quantity(*qty),
+            "total": spec.format_notional(*price, *qty)
         })).collect::<Vec<_>>(),
         "timestamp": Utc::now().timestamp()
     });
@@ -261,19 +913,36 @@ fn get_full_orderbook_snapshot(symbol: &Symbol) -> Result<serde_json::Value, Box
     Ok(orderbook_data)
 }
 
-fn get_orderbook_with_trades(symbol: &Symbol, trade_data: &serde_json::Value) -> Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>> {
-    let orderbook_snapshot = get_full_orderbook_snapshot(symbol)?;
-    
-    let combined_data = json!({
-        "orderbook": orderbook_snapshot,
-        "recent_trades": trade_data["trades"],
-        "trade_summary": {
-            "trades_count": trade_data["trades"].as_array().map(|t| t.len()).unwrap_or(0),
-            "last_price": trade_data["current_price"]
-        }
+/// Every level of a book with the sequence number it is current as of.
+fn get_depth_snapshot(symbol: &Symbol) -> Result<(u64, serde_json::Value), Box<dyn std::error::Error + Send + Sync>> {
+    let books = BOOKS.lock().unwrap();
+    let book = books.get(symbol).ok_or("Symbol not found")?;
+    let spec = &book.instrument.spec;
+
+    let depth = json!({
+        "bids": book.depth.bids.iter().rev().map(|(price, qty)| level_json(spec, *price, *qty)).collect::<Vec<_>>(),
+        "asks": book.depth.asks.iter().map(|(price, qty)| level_json(spec, *price, *qty)).collect::<Vec<_>>(),
+        "last_trade_price": book.depth.last_trade_price.map(|p| spec.format_price(p))
     });
-    
-    Ok(combined_data)
+    Ok((book.seq, depth))
+}
+
+/// Every resting order of a book, fetched from the worker, with the L3 event
+/// sequence it is current as of.
+async fn get_l3_snapshot(redis_client: &Client, symbol: &Symbol) -> Result<(u64, Value), Box<dyn std::error::Error + Send + Sync>> {
+    let response = request_worker(redis_client, json!({ "type": "l3_snapshot", "symbol": symbol })).await?;
+    if let Some(error) = response["error"].as_str() {
+        return Err(error.into());
+    }
+    let seq = response["seq"].as_u64().ok_or("L3 snapshot has no seq")?;
+    Ok((seq, json!({ "bids": response["bids"], "asks": response["asks"] })))
+}
+
+fn level_json(spec: &InstrumentSpec, price: Price, qty: Quantity) -> serde_json::Value {
+    json!({
+        "price": spec.format_price(price),
+        "quantity": spec.format_quantity(qty)
+    })
 }
 
 #[tokio::main]
@@ -283,19 +952,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = env::args().nth(1).unwrap_or_else(|| "127.0.0.1:4000".to_string());
     let addr: std::net::SocketAddr = addr.parse().expect("Invalid address");
     
+    let redis_client = Client::open("redis://127.0.0.1:6379/".to_string())?;
+    let events_client = redis_client.clone();
+    let trades_client = redis_client.clone();
+    let candles_client = redis_client.clone();
+    let connections_client = redis_client.clone();
+    // Before the trade relay starts, so no trade is counted twice
+    if let Err(e) = seed_tickers(&redis_client).await {
+        eprintln!("Ticker statistics start empty: {}", e);
+    }
+    tokio::spawn(async move {
+        if let Err(e) = replicate_depth(redis_client).await {
+            eprintln!("Depth replication stopped: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = relay_book_events(events_client).await {
+            eprintln!("L3 relay stopped: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = relay_trades(trades_client).await {
+            eprintln!("Trade relay stopped: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = relay_candles(candles_client).await {
+            eprintln!("Candle relay stopped: {}", e);
+        }
+    });
+
     println!("WebSocket server starting on: {}", addr);
     let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
     
     println!("WebSocket server listening on ws://{}", addr);
     println!("Available commands:");
     println!("  - subscribe: {{\"type\": \"subscribe\", \"symbol\": \"BTCUSD\"}}");
+    println!("  - subscribe (L3): {{\"type\": \"subscribe\", \"symbol\": \"BTCUSD\", \"level\": \"L3\"}}");
+    println!("  - subscribe (trades/ticker): {{\"type\": \"subscribe\", \"channel\": \"ticker\", \"symbol\": \"BTCUSD\"}}");
+    println!("  - subscribe (candles): {{\"type\": \"subscribe\", \"channel\": \"candles\", \"symbol\": \"BTCUSD\", \"interval\": \"1m\"}}");
     println!("  - unsubscribe: {{\"type\": \"unsubscribe\", \"symbol\": \"BTCUSD\"}}");
+    println!("  - resync: {{\"type\": \"resync\", \"symbol\": \"BTCUSD\"}} (add \"level\": \"L3\" for order events)");
     println!("  - get_orderbook: {{\"type\": \"get_orderbook\", \"symbol\": \"BTCUSD\"}}");
+    println!("  - price: {{\"type\": \"price\", \"symbol\": \"BTCUSD\"}}");
     println!("  - ping: {{\"type\": \"ping\"}}");
     
     while let Ok((stream, addr)) = listener.accept().await {
         println!("New connection from: {}", addr);
-        tokio::spawn(handle_connection(stream));
+        tokio::spawn(handle_connection(stream, connections_client.clone()));
     }
     
     Ok(())
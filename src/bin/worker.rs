@@ -1,6 +1,18 @@
-use orderbook::engine::service::process_order;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use orderbook::{
+    clock::{Clock, ManualClock, NANOS_PER_SEC, SystemClock},
+    config::APP_CONFIG,
+    engine::service::{account_balances, add_instrument, book_orders, capture_accounts, capture_books, drain_book_events, drain_ledger_entries, list_instruments, new_book_depths, process_cancel, process_expiries, process_deposit, process_modify, process_order, process_withdraw, replay_command, restore_books, set_instrument_status, touched_levels},
+    error::OrderError,
+    journal::{Journal, JournalEntry},
+    ledger::{self, Ledger},
+    units::{ASSET_DECIMALS, format_units},
+    snapshot::Snapshot,
+    inputs::{Balance, BookEventBatch, Candle, Fee, CandleInterval, CandleQuery, CandleStore, DepthFeedMessage, DepthSnapshot, DepthUpdate, InstrumentSpec, LevelChange, Order, OrderBookState, OrderStatus, Quantity, SelfTradePrevented, Side, Symbol, TimeInForce, Trade},
+};
 use rand::{ rngs::ThreadRng};
-use redis::{AsyncCommands, Client};
+use redis::{AsyncCommands, Client, aio::Connection};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use tokio::time::{Duration, sleep};
@@ -8,11 +20,187 @@ use tokio::time::{Duration, sleep};
 #[derive(Debug, Serialize, Deserialize)]
 struct OrderResponse {
     result_id: u64,
+    status: OrderStatus,
+    time_in_force: TimeInForce,
+    repriced_price: Option<String>,
     trades: Vec<serde_json::Value>,
-    remaining_quantity: u64,
-    current_price: Option<f64>,
-    best_bid: Option<f64>,
-    best_ask: Option<f64>,
+    /// Net fees charged to this order across its fills, by asset; negative for rebates
+    fees: BTreeMap<String, String>,
+    self_trades_prevented: Vec<serde_json::Value>,
+    remaining_quantity: String,
+    current_price: Option<String>,
+    best_bid: Option<String>,
+    best_ask: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CancelResponse {
+    order_id: u64,
+    cancelled_quantity: String,
+    best_bid: Option<String>,
+    best_ask: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ModifyResponse {
+    order_id: u64,
+    trades: Vec<serde_json::Value>,
+    self_trades_prevented: Vec<serde_json::Value>,
+    filled_quantity: String,
+    remaining_quantity: String,
+    current_price: Option<String>,
+    best_bid: Option<String>,
+    best_ask: Option<String>,
+}
+
+/// Depth last published on `depth_updates` for each book. A book's first
+/// appearance is published as a snapshot, later changes as sequenced diffs of
+/// the levels its L3 events touched.
+#[derive(Default)]
+struct DepthFeed {
+    books: HashMap<Symbol, DepthSnapshot>,
+}
+
+impl DepthFeed {
+    async fn publish(&mut self, conn: &mut Connection, batches: &[BookEventBatch]) -> Result<(), Box<dyn std::error::Error>> {
+        let published: HashSet<Symbol> = self.books.keys().cloned().collect();
+        for (instrument, depth) in new_book_depths(&published) {
+            let snapshot = DepthSnapshot { instrument: instrument.to_config(), depth };
+            let message = DepthFeedMessage::Snapshot(Box::new(snapshot.clone()));
+            let _: () = conn.publish("depth_updates", serde_json::to_string(&message)?).await?;
+            self.books.insert(instrument.symbol, snapshot);
+        }
+
+        for batch in batches {
+            let (Some(last), Some((levels, last_trade_price))) = (self.books.get_mut(&batch.symbol), touched_levels(batch)) else {
+                continue;
+            };
+            let changes: Vec<LevelChange> = levels
+                .into_iter()
+                .filter(|change| {
+                    let levels = match change.side {
+                        Side::Buy => &last.depth.bids,
+                        Side::Sell => &last.depth.asks,
+                    };
+                    levels.get(&change.price).copied().unwrap_or_default() != change.quantity
+                })
+                .collect();
+            if changes.is_empty() && last.depth.last_trade_price == last_trade_price {
+                continue;
+            }
+
+            let update = DepthUpdate {
+                symbol: batch.symbol.clone(),
+                seq: last.depth.seq + 1,
+                changes,
+                last_trade_price,
+            };
+            let _: () = conn.publish("depth_updates", serde_json::to_string(&DepthFeedMessage::Update(update.clone()))?).await?;
+            last.depth.apply(&update);
+        }
+        Ok(())
+    }
+
+    /// Snapshots as of the last publish, so a subscriber can resume from their `seq`.
+    fn snapshot_response(&self, request_json: &Value) -> Result<String, Box<dyn std::error::Error>> {
+        let symbol = request_json["symbol"].as_str();
+        let books: Vec<&DepthSnapshot> = self.books
+            .values()
+            .filter(|book| symbol.is_none_or(|s| book.depth.symbol.0 == s))
+            .collect();
+        Ok(json!({ "books": books }).to_string())
+    }
+}
+
+/// In-progress and recent closed bars kept per symbol and interval.
+const MAX_CANDLES: usize = 1000;
+
+fn candle_key(symbol: &Symbol, interval: CandleInterval) -> String {
+    format!("candles:{}:{}", symbol, interval.as_str())
+}
+
+/// Key holding the in-progress bar of a series, next to its closed bars.
+fn open_candle_key(symbol: &Symbol, interval: CandleInterval) -> String {
+    format!("{}:open", candle_key(symbol, interval))
+}
+
+/// Refills the in-memory history from the bars persisted in Redis, including
+/// the bar that was still in progress when the worker stopped.
+async fn load_candles(conn: &mut Connection, candles: &mut CandleStore) -> Result<(), Box<dyn std::error::Error>> {
+    for instrument in list_instruments() {
+        for interval in CandleInterval::ALL {
+            let stored: Vec<String> = conn
+                .zrange(candle_key(&instrument.symbol, interval), -(MAX_CANDLES as isize), -1)
+                .await?;
+            let mut bars: Vec<Candle> = stored.iter().filter_map(|c| serde_json::from_str(c).ok()).collect();
+            let open: Option<String> = conn.get(open_candle_key(&instrument.symbol, interval)).await?;
+            if let Some(open) = open.and_then(|c| serde_json::from_str::<Candle>(&c).ok())
+                && bars.last().is_none_or(|last| last.open_time < open.open_time)
+            {
+                bars.push(open);
+            }
+            candles.load(bars);
+        }
+    }
+    Ok(())
+}
+
+/// Requests that change book state and so are journaled before they are applied.
+fn is_journaled(request_type: Option<&str>) -> bool {
+    !matches!(
+        request_type,
+        Some("list_instruments") | Some("depth_snapshot") | Some("l3_snapshot") | Some("candles") | Some("balances") | Some("audit")
+    )
+}
+
+/// Rebuilds the books, order id counter and accounts from the latest snapshot plus the
+/// journal after it, then opens the journal for appending. Ledger entries of
+/// replayed commands that were not persisted before the restart are written now.
+fn recover_books() -> Result<(Journal, Ledger), Box<dyn std::error::Error>> {
+    let from = match Snapshot::read(&APP_CONFIG.snapshot_path)? {
+        Some(snapshot) => {
+            println!(
+                "📸 Loaded snapshot {} ({} books, journal offset {})",
+                APP_CONFIG.snapshot_path,
+                snapshot.books.len(),
+                snapshot.journal_offset
+            );
+            let offset = snapshot.journal_offset;
+            restore_books(snapshot.books, snapshot.next_order_id, snapshot.accounts);
+            offset
+        }
+        None => 0,
+    };
+
+    let path = &APP_CONFIG.journal_path;
+    let entries = Journal::recover(path, from)?;
+    for entry in &entries {
+        let _ = replay_command(&entry.command, &entry.clock());
+    }
+    // These events were published before the restart; the depth feed starts from a snapshot
+    drain_book_events();
+    println!("♻️  Replayed {} journaled commands from {}", entries.len(), path);
+
+    let mut ledger = Ledger::open(&APP_CONFIG.ledger_path)?;
+    ledger.append(&drain_ledger_entries())?;
+    Ok((Journal::open(path)?, ledger))
+}
+
+/// Snapshots every book and the accounts at the journal's current offset, then drops the
+/// journal records the snapshot covers.
+fn take_snapshot(journal: &mut Journal) -> Result<(), Box<dyn std::error::Error>> {
+    let (books, next_order_id, accounts) = capture_books();
+    let snapshot = Snapshot {
+        taken_at: chrono::Utc::now().timestamp(),
+        journal_offset: journal.offset(),
+        next_order_id,
+        books,
+        accounts,
+    };
+    snapshot.write(&APP_CONFIG.snapshot_path)?;
+    journal.truncate()?;
+    println!("📸 Wrote snapshot at journal offset {}", snapshot.journal_offset);
+    Ok(())
 }
 
 #[tokio::main]
@@ -21,9 +209,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let redis_client = Client::open(redis_url)?;
     let mut conn = redis_client.get_async_connection().await?;
     let mut _rng: ThreadRng = rand::thread_rng();
+    let (mut journal, mut ledger) = recover_books()?;
+    let mut snapshot_offset = journal.offset();
+    let mut last_snapshot = tokio::time::Instant::now();
+    let mut depth_feed = DepthFeed::default();
+    let mut candles = CandleStore::new(MAX_CANDLES);
+    load_candles(&mut conn, &mut candles).await?;
 
     loop {
-        let result: redis::RedisResult<Vec<String>> = conn.blpop("order", 0).await;
+        let batches = drain_book_events();
+        depth_feed.publish(&mut conn, &batches).await?;
+        publish_book_events(&mut conn, &batches).await?;
+
+        // Every event so far has been numbered and published, so the snapshot
+        // starts the numbering after them
+        if journal.offset() > snapshot_offset
+            && last_snapshot.elapsed() >= Duration::from_secs(APP_CONFIG.snapshot_interval_secs)
+        {
+            take_snapshot(&mut journal)?;
+            snapshot_offset = journal.offset();
+            last_snapshot = tokio::time::Instant::now();
+        }
+
+        // Time out once a second so GTD/DAY expiries are swept even when idle
+        let result: redis::RedisResult<Vec<String>> = conn.blpop("order", 1).await;
         match result {
             Ok(items) => {
                 if items.len() >= 2 {
@@ -32,59 +241,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let order_json: Value = serde_json::from_str(order_data)?;
                     let request_id = order_json["request_id"].as_str().unwrap_or("unknown");
 
-                    match process_order(&order_json) {
-                        Ok(result) => {
-                            // let n: u32 = rng.gen_range(1..=100);
-                            let response = OrderResponse {
-                                result_id: result.order_id,
-                                trades: result.trades.iter()
-                                    .map(|t| serde_json::to_value(t).unwrap())
-                                    .collect(),
-                                remaining_quantity: result.remaining_quantity,
-                                current_price: result.orderbook_state.current_price
-                                    .map(|p| p as f64 / 100.0),
-                                best_bid: result.orderbook_state.best_bid
-                                    .map(|p| p as f64 / 100.0),
-                                best_ask: result.orderbook_state.best_ask
-                                    .map(|p| p as f64 / 100.0),
-                            };
-                            
-                            if !result.trades.is_empty() {
-                                let market_update = serde_json::json!({
-                                    "symbol": order_json["symbol"].as_str().unwrap_or("unknown"),
-                                    "trades": result.trades.iter().map(|trade| json!({
-                                        "id": trade.id,
-                                        "price": trade.price as f64 / 100.0,
-                                        "quantity": trade.qty,
-                                        "timestamp": trade.time,
-                                        "side": if trade.is_buy { "buy" } else { "sell" }
-                                    })).collect::<Vec<_>>(),
-                                    "current_price": result.orderbook_state.current_price.map(|p| p as f64 / 100.0),
-                                    "best_bid": result.orderbook_state.best_bid.map(|p| p as f64 / 100.0),
-                                    "best_ask": result.orderbook_state.best_ask.map(|p| p as f64 / 100.0),
-                                    "timestamp": chrono::Utc::now().timestamp()
-                                });
-                                
-                                let _: () = conn.publish("market_updates", market_update.to_string()).await?;
-                                println!("📡 Published market update for {:?}", order_json["symbol"].as_str().unwrap_or("unknown"));
-                            }
-                            println!("{:?}",response);
-
-                            let json = serde_json::to_string(&response)?;
-
-                            let response_channel = format!("order_response:{}", request_id);
-                            let _: () = conn.publish(&response_channel, json).await?;
-                        }
-                        Err(e)=>{
-                            eprintln!("Error processing order :{}",e);
-                            let error_response = serde_json::json!({
-                                "error": e.to_string(),
-                                "result_id": 0
-                            });
-                            let response_channel = format!("order_response:{}", request_id);
-                            let _: () = conn.publish(&response_channel, error_response.to_string()).await?;
-                        }
+                    // Stopped for the whole command, so the journaled time is the time the engine sees
+                    let clock = ManualClock::new(SystemClock.now());
+                    if is_journaled(order_json["type"].as_str()) {
+                        journal.append(&JournalEntry { timestamp: clock.now(), command: order_json.clone() })?;
                     }
+
+                    let response = match order_json["type"].as_str() {
+                        Some("cancel") => handle_cancel(&order_json)?,
+                        Some("modify") => handle_modify(&mut conn, &order_json, &clock, &mut candles).await?,
+                        Some("list_instruments") | Some("add_instrument") | Some("set_instrument_status") => {
+                            handle_instrument_admin(&order_json)?
+                        }
+                        Some("depth_snapshot") => depth_feed.snapshot_response(&order_json)?,
+                        Some("l3_snapshot") => handle_l3_snapshot(&order_json)?,
+                        Some("candles") => handle_candles(&mut conn, &order_json, &candles).await?,
+                        Some("deposit") | Some("withdraw") | Some("balances") => handle_account(&order_json, &clock)?,
+                        Some("audit") => handle_audit()?,
+                        _ => handle_order(&mut conn, &order_json, &clock, &mut candles).await?,
+                    };
+
+                    ledger.append(&drain_ledger_entries())?;
+                    let response_channel = format!("order_response:{}", request_id);
+                    let _: () = conn.publish(&response_channel, response).await?;
                 }
             }
             Err(e) => {
@@ -92,5 +271,420 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 sleep(Duration::from_secs(5)).await;
             }
         }
+
+        let clock = ManualClock::new(SystemClock.now());
+        let expired = process_expiries(&clock);
+        if !expired.is_empty() {
+            // A sweep depends only on the books and the time, so it is safe to journal after the fact
+            journal.append(&JournalEntry { timestamp: clock.now(), command: json!({ "type": "expire" }) })?;
+        }
+        for order in expired {
+            println!("⌛ Expired order {} ({} units remaining)", order.id, order.total_qty().0);
+        }
+
+        let closed = candles.close_expired(clock.now_secs());
+        publish_candles(&mut conn, &closed).await?;
+    }
+}
+
+async fn handle_order(conn: &mut Connection, order_json: &Value, clock: &dyn Clock, candles: &mut CandleStore) -> Result<String, Box<dyn std::error::Error>> {
+    match process_order(order_json, clock) {
+        Ok(result) => {
+            // let n: u32 = rng.gen_range(1..=100);
+            let state = &result.orderbook_state;
+            let spec = &state.spec;
+            let response = OrderResponse {
+                result_id: result.order_id,
+                status: result.status,
+                time_in_force: result.time_in_force,
+                repriced_price: result.repriced_price.map(|p| spec.format_price(p)),
+                trades: result.trades.iter()
+                    .map(|t| trade_json(t, spec))
+                    .collect(),
+                fees: order_fees(result.order_id, &result.trades),
+                self_trades_prevented: result.self_trades_prevented.iter()
+                    .map(|p| self_trade_json(p, spec))
+                    .collect(),
+                remaining_quantity: spec.format_quantity(result.remaining_quantity),
+                current_price: state.current_price.map(|p| spec.format_price(p)),
+                best_bid: state.best_bid.map(|p| spec.format_price(p)),
+                best_ask: state.best_ask.map(|p| spec.format_price(p)),
+            };
+
+            publish_market_update(conn, &result.trades, state).await?;
+            record_candles(conn, candles, &result.trades, state).await?;
+            println!("{:?}",response);
+
+            Ok(serde_json::to_string(&response)?)
+        }
+        Err(e)=>{
+            eprintln!("Error processing order :{}",e);
+            let error_response = serde_json::json!({
+                "error": e.to_string(),
+                "code": error_code(e.as_ref()),
+                "result_id": 0
+            });
+            Ok(error_response.to_string())
+        }
     }
 }
+
+fn handle_cancel(cancel_json: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    match process_cancel(cancel_json) {
+        Ok(result) => {
+            let state = &result.orderbook_state;
+            let spec = &state.spec;
+            let response = CancelResponse {
+                order_id: result.order_id,
+                cancelled_quantity: spec.format_quantity(result.cancelled_quantity),
+                best_bid: state.best_bid.map(|p| spec.format_price(p)),
+                best_ask: state.best_ask.map(|p| spec.format_price(p)),
+            };
+            println!("{:?}",response);
+
+            Ok(serde_json::to_string(&response)?)
+        }
+        Err(e)=>{
+            eprintln!("Error cancelling order :{}",e);
+            let error_response = serde_json::json!({
+                "error": e.to_string(),
+                "code": error_code(e.as_ref()),
+                "order_id": cancel_json["order_id"]
+            });
+            Ok(error_response.to_string())
+        }
+    }
+}
+
+async fn handle_modify(conn: &mut Connection, modify_json: &Value, clock: &dyn Clock, candles: &mut CandleStore) -> Result<String, Box<dyn std::error::Error>> {
+    match process_modify(modify_json, clock) {
+        Ok(result) => {
+            let state = &result.orderbook_state;
+            let spec = &state.spec;
+            let filled: Quantity = result.trades.iter().map(|t| t.qty).sum();
+            let response = ModifyResponse {
+                order_id: result.order_id,
+                trades: result.trades.iter()
+                    .map(|t| trade_json(t, spec))
+                    .collect(),
+                self_trades_prevented: result.self_trades_prevented.iter()
+                    .map(|p| self_trade_json(p, spec))
+                    .collect(),
+                filled_quantity: spec.format_quantity(filled),
+                remaining_quantity: spec.format_quantity(result.remaining_quantity),
+                current_price: state.current_price.map(|p| spec.format_price(p)),
+                best_bid: state.best_bid.map(|p| spec.format_price(p)),
+                best_ask: state.best_ask.map(|p| spec.format_price(p)),
+            };
+
+            publish_market_update(conn, &result.trades, state).await?;
+            record_candles(conn, candles, &result.trades, state).await?;
+            println!("{:?}",response);
+
+            Ok(serde_json::to_string(&response)?)
+        }
+        Err(e)=>{
+            eprintln!("Error modifying order :{}",e);
+            let error_response = serde_json::json!({
+                "error": e.to_string(),
+                "code": error_code(e.as_ref()),
+                "order_id": modify_json["order_id"]
+            });
+            Ok(error_response.to_string())
+        }
+    }
+}
+
+fn handle_instrument_admin(admin_json: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let result = match admin_json["type"].as_str() {
+        Some("add_instrument") => add_instrument(admin_json).map(|i| json!({ "instrument": i.to_config() })),
+        Some("set_instrument_status") => set_instrument_status(admin_json).map(|i| json!({ "instrument": i.to_config() })),
+        _ => Ok(json!({
+            "instruments": list_instruments().iter().map(|i| i.to_config()).collect::<Vec<_>>()
+        })),
+    };
+
+    match result {
+        Ok(response) => {
+            println!("🛠  {}", response);
+            Ok(response.to_string())
+        }
+        Err(e) => {
+            eprintln!("Error updating instruments :{}", e);
+            let error_response = serde_json::json!({
+                "error": e.to_string(),
+                "code": error_code(e.as_ref())
+            });
+            Ok(error_response.to_string())
+        }
+    }
+}
+
+fn handle_account(account_json: &Value, clock: &dyn Clock) -> Result<String, Box<dyn std::error::Error>> {
+    let result = match account_json["type"].as_str() {
+        Some("deposit") => process_deposit(account_json, clock),
+        Some("withdraw") => process_withdraw(account_json, clock),
+        _ => {
+            let user_id = account_json["user_id"].as_u64().unwrap_or(0) as u32;
+            let balances: Vec<Value> = account_balances(user_id)
+                .iter()
+                .map(|(asset, balance)| balance_json(asset, balance))
+                .collect();
+            return Ok(json!({ "user_id": user_id, "balances": balances }).to_string());
+        }
+    };
+
+    match result {
+        Ok(result) => {
+            let mut response = balance_json(&result.asset, &result.balance);
+            response["user_id"] = json!(result.user_id);
+            println!("💰 {} {}", account_json["type"], response);
+            Ok(response.to_string())
+        }
+        Err(e) => {
+            eprintln!("Error updating account :{}", e);
+            let error_response = serde_json::json!({
+                "error": e.to_string(),
+                "code": error_code(e.as_ref())
+            });
+            Ok(error_response.to_string())
+        }
+    }
+}
+
+/// Recomputes balances from the persisted ledger and compares them with the
+/// live accounts. Every earlier command's entries have been appended by now.
+fn handle_audit() -> Result<String, Box<dyn std::error::Error>> {
+    let entries = Ledger::read(&APP_CONFIG.ledger_path)?;
+    let problems = ledger::audit(&entries, &capture_accounts());
+    println!("🔎 Audited {} ledger entries: {} problem(s)", entries.len(), problems.len());
+    Ok(json!({
+        "entries": entries.len(),
+        "ok": problems.is_empty(),
+        "problems": problems
+    })
+    .to_string())
+}
+
+/// Every order resting in a book with the L3 event `seq` it is current as of,
+/// for a client starting or resyncing an order-level feed.
+fn handle_l3_snapshot(request_json: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    let symbol = Symbol::from(request_json["symbol"].as_str().unwrap_or_default());
+    match book_orders(&symbol) {
+        Ok(book) => {
+            let spec = &book.spec;
+            let orders = |orders: &[Order]| -> Vec<Value> {
+                orders
+                    .iter()
+                    .map(|order| json!({
+                        "order_id": order.id,
+                        "price": spec.format_price(order.price),
+                        "quantity": spec.format_quantity(order.qty)
+                    }))
+                    .collect()
+            };
+            Ok(json!({
+                "symbol": book.symbol,
+                "seq": book.seq,
+                "bids": orders(&book.bids),
+                "asks": orders(&book.asks)
+            })
+            .to_string())
+        }
+        Err(e) => Ok(json!({ "error": e.to_string(), "code": e.code() }).to_string()),
+    }
+}
+
+fn balance_json(asset: &str, balance: &Balance) -> Value {
+    json!({
+        "asset": asset,
+        "available": format_units(balance.available.0 as u128, ASSET_DECIMALS),
+        "locked": format_units(balance.locked.0 as u128, ASSET_DECIMALS)
+    })
+}
+
+async fn handle_candles(conn: &mut Connection, request_json: &Value, candles: &CandleStore) -> Result<String, Box<dyn std::error::Error>> {
+    match candle_history(conn, request_json, candles).await {
+        Ok(response) => Ok(response.to_string()),
+        Err(e) => {
+            eprintln!("Error loading candles :{}", e);
+            let error_response = serde_json::json!({
+                "error": e.to_string(),
+                "code": error_code(e.as_ref())
+            });
+            Ok(error_response.to_string())
+        }
+    }
+}
+
+/// Bars in the requested range, served from memory when it reaches back far
+/// enough and from the persisted closed bars otherwise.
+async fn candle_history(conn: &mut Connection, request_json: &Value, candles: &CandleStore) -> Result<Value, Box<dyn std::error::Error>> {
+    let query: CandleQuery = serde_json::from_value(request_json.clone())?;
+    let instrument = list_instruments()
+        .into_iter()
+        .find(|i| i.symbol == query.symbol)
+        .ok_or(OrderError::UnknownSymbol)?;
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(i64::MAX);
+
+    let in_memory = candles.query(&query.symbol, query.interval, from, to);
+    let bars = if candles.oldest(&query.symbol, query.interval).is_some_and(|oldest| oldest <= from) {
+        in_memory
+    } else {
+        let stored: Vec<String> = conn.zrangebyscore(candle_key(&query.symbol, query.interval), from, to).await?;
+        let mut bars: Vec<Candle> = stored.iter().filter_map(|c| serde_json::from_str(c).ok()).collect();
+        bars.extend(in_memory.into_iter().filter(|c| !c.closed));
+        bars
+    };
+
+    Ok(json!({
+        "symbol": query.symbol,
+        "interval": query.interval,
+        "candles": bars.iter().map(|c| candle_json(c, &instrument.spec)).collect::<Vec<_>>()
+    }))
+}
+
+/// Folds trades into the candles and publishes every bar they touched.
+async fn record_candles(conn: &mut Connection, candles: &mut CandleStore, trades: &[Trade], state: &OrderBookState) -> Result<(), Box<dyn std::error::Error>> {
+    let mut updated = Vec::new();
+    for trade in trades {
+        let timestamp = trade.time.div_euclid(NANOS_PER_SEC);
+        updated.extend(candles.record_trade(&state.symbol, trade.price, trade.qty, timestamp));
+    }
+    publish_candles(conn, &updated).await
+}
+
+/// Publishes bar updates on `candles` and persists them: closed bars in the
+/// series, the in-progress bar under its own key until it closes.
+async fn publish_candles(conn: &mut Connection, candles: &[Candle]) -> Result<(), Box<dyn std::error::Error>> {
+    if candles.is_empty() {
+        return Ok(());
+    }
+    let instruments = list_instruments();
+    for candle in candles {
+        let Some(instrument) = instruments.iter().find(|i| i.symbol == candle.symbol) else {
+            continue;
+        };
+        let _: () = conn.publish("candles", candle_json(candle, &instrument.spec).to_string()).await?;
+        let open_key = open_candle_key(&candle.symbol, candle.interval);
+        if candle.closed {
+            let _: () = conn
+                .zadd(candle_key(&candle.symbol, candle.interval), serde_json::to_string(candle)?, candle.open_time)
+                .await?;
+            let _: () = conn.del(open_key).await?;
+        } else {
+            let _: () = conn.set(open_key, serde_json::to_string(candle)?).await?;
+        }
+    }
+    Ok(())
+}
+
+fn candle_json(candle: &Candle, spec: &InstrumentSpec) -> Value {
+    json!({
+        "symbol": candle.symbol,
+        "interval": candle.interval,
+        "open_time": candle.open_time,
+        "close_time": candle.close_time(),
+        "open": spec.format_price(candle.open),
+        "high": spec.format_price(candle.high),
+        "low": spec.format_price(candle.low),
+        "close": spec.format_price(candle.close),
+        "volume": spec.format_quantity(candle.volume),
+        "trade_count": candle.trade_count,
+        "closed": candle.closed
+    })
+}
+
+/// Publishes each book's L3 events on its own `book_events:{symbol}` channel.
+async fn publish_book_events(conn: &mut Connection, batches: &[BookEventBatch]) -> Result<(), Box<dyn std::error::Error>> {
+    for batch in batches {
+        let channel = format!("book_events:{}", batch.symbol);
+        let _: () = conn.publish(&channel, serde_json::to_string(&batch)?).await?;
+    }
+    Ok(())
+}
+
+async fn publish_market_update(conn: &mut Connection, trades: &[Trade], state: &OrderBookState) -> Result<(), Box<dyn std::error::Error>> {
+    if trades.is_empty() {
+        return Ok(());
+    }
+    let spec = &state.spec;
+    let symbol = state.symbol.to_string();
+    let market_update = serde_json::json!({
+        "symbol": symbol,
+        "trades": trades.iter().map(|trade| json!({
+            "trade_id": trade.trade_id,
+            "price": spec.format_price(trade.price),
+            "quantity": spec.format_quantity(trade.qty),
+            "timestamp": trade.time,
+            "side": match trade.aggressor_side { Side::Buy => "buy", Side::Sell => "sell" }
+        })).collect::<Vec<_>>(),
+        "current_price": state.current_price.map(|p| spec.format_price(p)),
+        "best_bid": state.best_bid.map(|p| spec.format_price(p)),
+        "best_ask": state.best_ask.map(|p| spec.format_price(p)),
+        "timestamp": chrono::Utc::now().timestamp()
+    });
+
+    let _: () = conn.publish("market_updates", market_update.to_string()).await?;
+    println!("📡 Published market update for {}", symbol);
+    Ok(())
+}
+
+/// Trade as sent to clients, with price and quantity as decimal strings.
+fn trade_json(trade: &Trade, spec: &InstrumentSpec) -> Value {
+    json!({
+        "trade_id": trade.trade_id,
+        "symbol": trade.symbol,
+        "price": spec.format_price(trade.price),
+        "qty": spec.format_quantity(trade.qty),
+        "maker_order_id": trade.maker_order_id,
+        "taker_order_id": trade.taker_order_id,
+        "maker_user_id": trade.maker_user_id,
+        "taker_user_id": trade.taker_user_id,
+        "aggressor_side": trade.aggressor_side,
+        "time": trade.time,
+        "maker_fee": fee_json(&trade.maker_fee),
+        "taker_fee": fee_json(&trade.taker_fee)
+    })
+}
+
+fn fee_json(fee: &Fee) -> Value {
+    json!({
+        "asset": fee.asset,
+        "bps": fee.bps,
+        "amount": format_fee(fee.amount)
+    })
+}
+
+/// Fee totals of the fills `order_id` took part in, as maker or taker.
+fn order_fees(order_id: u64, trades: &[Trade]) -> BTreeMap<String, String> {
+    let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+    for trade in trades {
+        if trade.taker_order_id == order_id {
+            *totals.entry(trade.taker_fee.asset.clone()).or_default() += trade.taker_fee.amount;
+        }
+        if trade.maker_order_id == order_id {
+            *totals.entry(trade.maker_fee.asset.clone()).or_default() += trade.maker_fee.amount;
+        }
+    }
+    totals.into_iter().map(|(asset, amount)| (asset, format_fee(amount))).collect()
+}
+
+fn format_fee(amount: i64) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{}{}", sign, format_units(amount.unsigned_abs() as u128, ASSET_DECIMALS))
+}
+
+fn self_trade_json(prevented: &SelfTradePrevented, spec: &InstrumentSpec) -> Value {
+    json!({
+        "order_id": prevented.order_id,
+        "user_id": prevented.user_id,
+        "cancelled_qty": spec.format_quantity(prevented.cancelled_qty)
+    })
+}
+
+/// Stable rejection code for clients; anything that isn't an `OrderError` is a plain rejection.
+fn error_code(e: &(dyn std::error::Error + 'static)) -> &'static str {
+    e.downcast_ref::<OrderError>().map(|e| e.code()).unwrap_or("REJECTED")
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::Utc;
+
+pub const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Source of the current time for the engine, in unix nanoseconds. The engine
+/// never reads the system time itself, so a manual clock makes its output
+/// fully determined by its input.
+pub trait Clock {
+    fn now(&self) -> i64;
+
+    /// Whole unix seconds, the unit of GTD/DAY expiry times.
+    fn now_secs(&self) -> i64 {
+        self.now().div_euclid(NANOS_PER_SEC)
+    }
+}
+
+/// Wall-clock time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    }
+}
+
+/// A clock that only moves when told to, for replay, simulation and tests.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        Self { now: AtomicI64::new(now) }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, nanos: i64) {
+        self.now.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
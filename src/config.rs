@@ -1,11 +1,48 @@
 use serde::Deserialize;
 use lazy_static::lazy_static;
 
+use crate::inputs::{AccountConfig, InstrumentConfig, RiskConfig};
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
     pub server_addr: String,
     pub redis_url: String,
-    pub ws_addr:String
+    pub ws_addr:String,
+    #[serde(default)]
+    pub instruments:Vec<InstrumentConfig>,
+    /// Write-ahead journal the worker replays on startup
+    #[serde(default = "default_journal_path")]
+    pub journal_path:String,
+    /// Latest book snapshot; recovery replays only the journal after it
+    #[serde(default = "default_snapshot_path")]
+    pub snapshot_path:String,
+    #[serde(default = "default_snapshot_interval_secs")]
+    pub snapshot_interval_secs:u64,
+    /// Append-only ledger of every balance movement
+    #[serde(default = "default_ledger_path")]
+    pub ledger_path:String,
+    /// Starting balances, applied when the worker starts without a snapshot
+    #[serde(default)]
+    pub accounts:Vec<AccountConfig>,
+    /// Pre-trade limits checked before an order reaches its book
+    #[serde(default)]
+    pub risk:RiskConfig
+}
+
+fn default_journal_path() -> String {
+    "data/orderbook.journal".to_string()
+}
+
+fn default_snapshot_path() -> String {
+    "data/orderbook.snapshot".to_string()
+}
+
+fn default_ledger_path() -> String {
+    "data/orderbook.ledger".to_string()
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    60
 }
 lazy_static! {
     pub static ref SETTINGS: config::Config = config::Config::builder()
@@ -1,17 +1,29 @@
-use std::collections::BTreeMap;
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fmt};
 
 use crate::{
-    engine::service::{add_order, match_order}, 
+    engine::service::{add_order, add_stop_order, cancel_order, expire_orders, match_order, modify_order, trigger_stop_orders}, 
+    error::OrderError,
     global::ORDERBOOKS, 
-    inputs::{Order, OrderBook, OrderBookDepth, OrderBookState, OrderType, Price, Symbol}
+    inputs::{AccountConfig, Accounts, Amount, Balance, BookDepth, BookEvent, BookEventKind, Candle, CandleInterval, CandleStore, Decimal, DepthUpdate, EntrySide, Fee, FeeScheduleConfig, FeeTier, FeeTierConfig, LedgerAccount, LedgerEntry, Posting, Instrument, InstrumentConfig, InstrumentSpec, Order, OrderBook, OrderBookDepth, OrderBookState, OrderId, OrderLocation, OrderLock, OrderType, Price, PriceLevel, Quantity, LevelChange, Side, Symbol, Trade, UserOrders},
+    units::{ASSET_DECIMALS, format_units},
 };
 
 impl OrderBook {
-    pub fn new(symbol: Symbol) -> Self {
+    pub fn new(instrument: Instrument) -> Self {
         Self {
-            symbol,
+            instrument,
+            next_trade_id: 1,
             asks: BTreeMap::new(),
             bids: BTreeMap::new(),
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            order_index: HashMap::new(),
+            expiries: BTreeSet::new(),
+            user_orders: HashMap::new(),
+            self_trades_prevented: Vec::new(),
+            book_events: Vec::new(),
+            closed_orders: Vec::new(),
+            next_event_seq: 1,
             current_price: None, 
             current_best_ask: None,
             current_best_bid: None,
@@ -23,30 +35,189 @@ impl OrderBook {
         add_order(self, order);
     }
 
-    pub fn match_order(&mut self, incoming_order: &Order, order_type: OrderType) -> Vec<Order> {
-        match_order(self, incoming_order, order_type)
+    /// Inconsistencies between the price levels, the order index, the
+    /// per-user totals and the cached best prices; empty for a healthy book.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut orders = 0;
+        let mut user_orders: HashMap<u32, UserOrders> = HashMap::new();
+        let queues = [
+            (&self.bids, true, false),
+            (&self.asks, false, false),
+            (&self.buy_stops, true, true),
+            (&self.sell_stops, false, true),
+        ];
+
+        for (levels, is_buy, is_stop) in queues {
+            for (price, level) in levels {
+                if level.is_empty() {
+                    problems.push(format!("empty level left at {}", price.0));
+                }
+                if level.orders.iter().filter(|slot| slot.is_none()).count() != level.tombstones {
+                    problems.push(format!("level at {} miscounts its removed slots", price.0));
+                }
+                for (position, order) in (level.head..).zip(&level.orders) {
+                    let Some(order) = order else {
+                        continue;
+                    };
+                    orders += 1;
+                    let user = user_orders.entry(order.user_id).or_default();
+                    user.open += 1;
+                    if order.is_buy {
+                        user.buy_qty += order.total_qty();
+                    }
+                    let key = if is_stop { order.stop_price } else { Some(order.price) };
+                    if order.is_buy != is_buy || key != Some(*price) {
+                        problems.push(format!("order {} is queued at the wrong side or price", order.id));
+                    }
+                    if order.qty.is_zero() {
+                        problems.push(format!("order {} has no displayed quantity", order.id));
+                    }
+                    match self.order_index.get(&order.id) {
+                        Some(location)
+                            if location.is_buy == is_buy
+                                && location.is_stop == is_stop
+                                && location.price == *price
+                                && location.position == position => {}
+                        _ => problems.push(format!("order {} is missing from the index or indexed elsewhere", order.id)),
+                    }
+                }
+            }
+        }
+        if orders != self.order_index.len() {
+            problems.push(format!("index has {} entries for {} queued orders", self.order_index.len(), orders));
+        }
+        if user_orders != self.user_orders {
+            problems.push("per-user open orders do not match the queues".to_string());
+        }
+
+        let best_bid = self.bids.keys().next_back().copied();
+        let best_ask = self.asks.keys().next().copied();
+        if let (Some(bid), Some(ask)) = (best_bid, best_ask)
+            && bid >= ask
+        {
+            problems.push(format!("book is crossed: bid {} >= ask {}", bid.0, ask.0));
+        }
+        if self.current_best_bid != best_bid || self.current_best_ask != best_ask {
+            problems.push("cached best bid/ask do not match the levels".to_string());
+        }
+        problems
+    }
+
+    pub fn add_stop_order(&mut self, order: Order) {
+        add_stop_order(self, order);
+    }
+
+    pub fn trigger_stop_orders(&mut self, now: i64) -> Vec<Trade> {
+        trigger_stop_orders(self, now)
+    }
+
+    pub fn match_order(&mut self, incoming_order: &Order, order_type: OrderType, now: i64) -> Vec<Trade> {
+        match_order(self, incoming_order, order_type, now)
+    }
+
+    pub fn cancel_order(&mut self, order_id: u64) -> Option<Order> {
+        cancel_order(self, order_id)
+    }
+
+    pub fn expire_orders(&mut self, now: i64) -> Vec<Order> {
+        expire_orders(self, now)
     }
 
+    pub fn modify_order(&mut self, order_id: OrderId, price: Option<Price>, qty: Option<Quantity>, now: i64) -> Option<Vec<Trade>> {
+        modify_order(self, order_id, price, qty, now)
+    }
+
+    pub fn get_order(&self, order_id: OrderId) -> Option<&Order> {
+        let location = self.order_index.get(&order_id)?;
+        self.levels(location).get(&location.price)?.get(location.position)
+    }
+
+    /// The price levels an indexed order lives in.
+    pub fn levels(&self, location: &OrderLocation) -> &BTreeMap<Price, PriceLevel> {
+        match (location.is_stop, location.is_buy) {
+            (false, true) => &self.bids,
+            (false, false) => &self.asks,
+            (true, true) => &self.buy_stops,
+            (true, false) => &self.sell_stops,
+        }
+    }
+
+    pub fn levels_mut(&mut self, location: &OrderLocation) -> &mut BTreeMap<Price, PriceLevel> {
+        match (location.is_stop, location.is_buy) {
+            (false, true) => &mut self.bids,
+            (false, false) => &mut self.asks,
+            (true, true) => &mut self.buy_stops,
+            (true, false) => &mut self.sell_stops,
+        }
+    }
+
+    /// Aggregated displayed quantity per price; iceberg reserves are not included.
     pub fn get_depth(&self, levels: usize) -> OrderBookDepth {
-        let mut bids: Vec<(u64, u64)> = self
+        let bids: Vec<(Price, Quantity)> = self
             .bids
             .iter()
-            .map(|(price, orders)| (*price, orders.iter().map(|o| o.qty).sum()))
+            .rev() // Highest price first
+            .take(levels)
+            .map(|(price, level)| (*price, level.iter().map(|o| o.qty).sum()))
             .collect();
-        bids.sort_by(|a, b| b.0.cmp(&a.0)); // Sort by price descending
-        bids.truncate(levels);
 
-        let mut asks: Vec<(u64, u64)> = self
+        let asks: Vec<(Price, Quantity)> = self
             .asks
-            .iter()
-            .map(|(price, orders)| (*price, orders.iter().map(|o| o.qty).sum()))
+            .iter() // Lowest price first
+            .take(levels)
+            .map(|(price, level)| (*price, level.iter().map(|o| o.qty).sum()))
             .collect();
-        asks.sort_by(|a, b| a.0.cmp(&b.0)); // Sort by price ascending
-        asks.truncate(levels);
 
         OrderBookDepth { bids, asks }
     }
     
+    /// Every level of the book with its displayed quantity, unsequenced.
+    pub fn book_depth(&self) -> BookDepth {
+        let aggregate = |levels: &BTreeMap<Price, PriceLevel>| {
+            levels
+                .iter()
+                .map(|(price, level)| (*price, level.iter().map(|o| o.qty).sum()))
+                .collect()
+        };
+        BookDepth {
+            symbol: self.instrument.symbol.clone(),
+            seq: 0,
+            bids: aggregate(&self.bids),
+            asks: aggregate(&self.asks),
+            last_trade_price: self.last_trade_price,
+        }
+    }
+
+    /// Displayed quantity resting at one price; zero when there is no such level.
+    pub fn level_quantity(&self, side: Side, price: Price) -> Quantity {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.get(&price).map(|level| level.iter().map(|o| o.qty).sum()).unwrap_or_default()
+    }
+
+    /// Takes the buffered L3 events, numbering them in the order they happened.
+    pub fn drain_book_events(&mut self) -> Vec<BookEvent> {
+        let mut events = std::mem::take(&mut self.book_events);
+        for event in &mut events {
+            event.seq = self.next_event_seq;
+            self.next_event_seq += 1;
+        }
+        events
+    }
+
+    /// Takes the orders that have left the book since the last drain.
+    /// Orders that were requeued (e.g. by a modify) are left out.
+    pub fn drain_closed_orders(&mut self) -> Vec<OrderId> {
+        let mut closed = std::mem::take(&mut self.closed_orders);
+        closed.sort_unstable();
+        closed.dedup();
+        closed.retain(|id| !self.order_index.contains_key(id));
+        closed
+    }
+
     pub fn get_orderbook_snapshot(
         symbol: Symbol,
     ) -> Result<OrderBookState, Box<dyn std::error::Error>> {
@@ -54,7 +225,8 @@ impl OrderBook {
         let orderbook = orderbooks.get(&symbol).ok_or("Symbol not found")?;
 
         Ok(OrderBookState {
-            symbol: orderbook.symbol.clone(),
+            symbol: orderbook.instrument.symbol.clone(),
+            spec: orderbook.instrument.spec.clone(),
             current_price: orderbook.current_price,
             best_bid: orderbook.current_best_bid,
             best_ask: orderbook.current_best_ask,
@@ -62,3 +234,922 @@ impl OrderBook {
         })
     }
 }
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 6] = [
+        CandleInterval::S1,
+        CandleInterval::M1,
+        CandleInterval::M5,
+        CandleInterval::M15,
+        CandleInterval::H1,
+        CandleInterval::D1,
+    ];
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            CandleInterval::S1 => 1,
+            CandleInterval::M1 => 60,
+            CandleInterval::M5 => 5 * 60,
+            CandleInterval::M15 => 15 * 60,
+            CandleInterval::H1 => 60 * 60,
+            CandleInterval::D1 => 24 * 60 * 60,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CandleInterval::S1 => "1s",
+            CandleInterval::M1 => "1m",
+            CandleInterval::M5 => "5m",
+            CandleInterval::M15 => "15m",
+            CandleInterval::H1 => "1h",
+            CandleInterval::D1 => "1d",
+        }
+    }
+
+    /// Start of the bar containing `timestamp`.
+    pub fn bar_start(self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.seconds())
+    }
+}
+
+impl Candle {
+    pub fn close_time(&self) -> i64 {
+        self.open_time + self.interval.seconds()
+    }
+}
+
+impl CandleStore {
+    pub fn new(max_candles: usize) -> Self {
+        Self { series: HashMap::new(), max_candles }
+    }
+
+    /// Adds a trade to every interval's current bar. Returns the bars it changed,
+    /// including any bar it closed by starting a new one.
+    pub fn record_trade(&mut self, symbol: &Symbol, price: Price, qty: Quantity, timestamp: i64) -> Vec<Candle> {
+        let mut updated = Vec::new();
+        for interval in CandleInterval::ALL {
+            let open_time = interval.bar_start(timestamp);
+            let series = self.series.entry((symbol.clone(), interval)).or_default();
+
+            if let Some(current) = series.back_mut()
+                && !current.closed
+            {
+                if current.open_time == open_time {
+                    current.high = current.high.max(price);
+                    current.low = current.low.min(price);
+                    current.close = price;
+                    current.volume += qty;
+                    current.trade_count += 1;
+                    updated.push(current.clone());
+                    continue;
+                }
+                current.closed = true;
+                updated.push(current.clone());
+            }
+
+            let candle = Candle {
+                symbol: symbol.clone(),
+                interval,
+                open_time,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: qty,
+                trade_count: 1,
+                closed: false,
+            };
+            updated.push(candle.clone());
+            series.push_back(candle);
+            while series.len() > self.max_candles {
+                series.pop_front();
+            }
+        }
+        updated
+    }
+
+    /// Closes every in-progress bar whose interval has ended by `now`.
+    pub fn close_expired(&mut self, now: i64) -> Vec<Candle> {
+        self.series
+            .values_mut()
+            .filter_map(|series| series.back_mut())
+            .filter(|candle| !candle.closed && candle.close_time() <= now)
+            .map(|candle| {
+                candle.closed = true;
+                candle.clone()
+            })
+            .collect()
+    }
+
+    /// Bars with `open_time` in `from..=to`.
+    pub fn query(&self, symbol: &Symbol, interval: CandleInterval, from: i64, to: i64) -> Vec<Candle> {
+        self.series
+            .get(&(symbol.clone(), interval))
+            .map(|series| {
+                series
+                    .iter()
+                    .filter(|c| c.open_time >= from && c.open_time <= to)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Open time of the oldest bar still held in memory.
+    pub fn oldest(&self, symbol: &Symbol, interval: CandleInterval) -> Option<i64> {
+        self.series.get(&(symbol.clone(), interval))?.front().map(|c| c.open_time)
+    }
+
+    /// Seeds a series with previously persisted bars, oldest first.
+    pub fn load(&mut self, candles: Vec<Candle>) {
+        for candle in candles {
+            let series = self.series.entry((candle.symbol.clone(), candle.interval)).or_default();
+            series.push_back(candle);
+            while series.len() > self.max_candles {
+                series.pop_front();
+            }
+        }
+    }
+}
+
+impl BookDepth {
+    /// Levels that differ in `next`, with removed levels reported at zero quantity.
+    pub fn changes_to(&self, next: &BookDepth) -> Vec<LevelChange> {
+        let side_changes = |side: Side, before: &BTreeMap<Price, Quantity>, after: &BTreeMap<Price, Quantity>| {
+            let removed = before
+                .keys()
+                .filter(|price| !after.contains_key(price))
+                .map(|price| LevelChange { side, price: *price, quantity: Quantity::ZERO });
+            let changed = after
+                .iter()
+                .filter(|(price, qty)| before.get(price) != Some(qty))
+                .map(|(price, qty)| LevelChange { side, price: *price, quantity: *qty });
+            removed.chain(changed).collect::<Vec<_>>()
+        };
+        let mut changes = side_changes(Side::Buy, &self.bids, &next.bids);
+        changes.extend(side_changes(Side::Sell, &self.asks, &next.asks));
+        changes
+    }
+
+    pub fn apply(&mut self, update: &DepthUpdate) {
+        for change in &update.changes {
+            let levels = match change.side {
+                Side::Buy => &mut self.bids,
+                Side::Sell => &mut self.asks,
+            };
+            if change.quantity.is_zero() {
+                levels.remove(&change.price);
+            } else {
+                levels.insert(change.price, change.quantity);
+            }
+        }
+        self.seq = update.seq;
+        self.last_trade_price = update.last_trade_price;
+    }
+
+    /// The best `levels` prices on each side.
+    pub fn top(&self, levels: usize) -> OrderBookDepth {
+        OrderBookDepth {
+            bids: self.bids.iter().rev().take(levels).map(|(p, q)| (*p, *q)).collect(),
+            asks: self.asks.iter().take(levels).map(|(p, q)| (*p, *q)).collect(),
+        }
+    }
+}
+
+impl BookEvent {
+    /// An event for a resting order; `seq` is filled in by `drain_book_events`.
+    pub fn new(kind: BookEventKind, order: &Order, qty: Quantity) -> Self {
+        Self {
+            seq: 0,
+            kind,
+            order_id: order.id,
+            side: order.side(),
+            price: order.price,
+            qty,
+            trade_id: None,
+        }
+    }
+}
+
+impl Order {
+    pub fn side(&self) -> Side {
+        if self.is_buy { Side::Buy } else { Side::Sell }
+    }
+
+    /// Displayed plus hidden quantity still open.
+    pub fn total_qty(&self) -> Quantity {
+        self.qty + self.hidden_qty
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(symbol: &str) -> Self {
+        Symbol(symbol.to_string())
+    }
+}
+
+impl Instrument {
+    /// Builds an instrument from its config entry, taking price and quantity
+    /// precision from the decimals of the tick and lot sizes.
+    pub fn from_config(config: &InstrumentConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        if config.symbol.0.is_empty() {
+            return Err("symbol must not be empty".into());
+        }
+        let price_decimals = config.tick_size.scale();
+        let quantity_decimals = config.lot_size.scale();
+        let units = |value: &Decimal, decimals: u32, name: &str| -> Result<u64, String> {
+            match value.to_units(decimals) {
+                Some(units) if units > 0 => Ok(units),
+                _ => Err(format!("{} must be a positive amount with at most {} decimals, got {}", name, decimals, value)),
+            }
+        };
+
+        let tick_size = Price(units(&config.tick_size, price_decimals, "tick_size")?);
+        let lot_size = Quantity(units(&config.lot_size, quantity_decimals, "lot_size")?);
+        let min_quantity = match &config.min_quantity {
+            Some(min_quantity) => Quantity(units(min_quantity, quantity_decimals, "min_quantity")?),
+            None => lot_size,
+        };
+        let max_quantity = Quantity(units(&config.max_quantity, quantity_decimals, "max_quantity")?);
+        if min_quantity < lot_size || max_quantity < min_quantity {
+            return Err("quantities must satisfy lot_size <= min_quantity <= max_quantity".into());
+        }
+        if price_decimals + quantity_decimals > ASSET_DECIMALS {
+            return Err(format!("tick_size and lot_size may have at most {} decimals between them", ASSET_DECIMALS).into());
+        }
+        let fee_tiers = fee_tiers(&config.fees)?;
+
+        Ok(Self {
+            symbol: config.symbol.clone(),
+            base_asset: config.base_asset.clone(),
+            quote_asset: config.quote_asset.clone(),
+            spec: InstrumentSpec {
+                price_decimals,
+                quantity_decimals,
+                tick_size,
+                lot_size,
+                min_quantity,
+                max_quantity,
+            },
+            status: config.status,
+            fee_tiers,
+        })
+    }
+
+    /// The config entry that recreates this instrument, with decimal amounts.
+    pub fn to_config(&self) -> InstrumentConfig {
+        InstrumentConfig {
+            symbol: self.symbol.clone(),
+            base_asset: self.base_asset.clone(),
+            quote_asset: self.quote_asset.clone(),
+            tick_size: Decimal::from(self.spec.format_price(self.spec.tick_size)),
+            lot_size: Decimal::from(self.spec.format_quantity(self.spec.lot_size)),
+            min_quantity: Some(Decimal::from(self.spec.format_quantity(self.spec.min_quantity))),
+            max_quantity: Decimal::from(self.spec.format_quantity(self.spec.max_quantity)),
+            status: self.status,
+            fees: FeeScheduleConfig {
+                maker_bps: self.fee_tiers[0].maker_bps,
+                taker_bps: self.fee_tiers[0].taker_bps,
+                tiers: self.fee_tiers[1..]
+                    .iter()
+                    .map(|tier| FeeTierConfig {
+                        min_volume: Decimal::from(format_units(tier.min_volume.0 as u128, ASSET_DECIMALS)),
+                        maker_bps: tier.maker_bps,
+                        taker_bps: tier.taker_bps,
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    /// The rates for a user with `volume` traded over the trailing 30 days.
+    pub fn fee_tier(&self, volume: Amount) -> FeeTier {
+        self.fee_tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume <= volume)
+            .copied()
+            .unwrap_or(FeeTier { min_volume: Amount::ZERO, maker_bps: 0, taker_bps: 0 })
+    }
+}
+
+/// Parses a fee schedule into tiers ordered by volume. Rates are limited to
+/// ±100%, and since maker and taker may sit in different tiers, no tier's
+/// maker rebate may exceed the lowest taker fee it could be paid from.
+fn fee_tiers(config: &FeeScheduleConfig) -> Result<Vec<FeeTier>, Box<dyn std::error::Error>> {
+    let mut tiers = vec![FeeTier { min_volume: Amount::ZERO, maker_bps: config.maker_bps, taker_bps: config.taker_bps }];
+    for tier in &config.tiers {
+        let min_volume = tier
+            .min_volume
+            .to_units(ASSET_DECIMALS)
+            .filter(|volume| *volume > 0)
+            .ok_or_else(|| format!("fee tier min_volume must be a positive amount, got {}", tier.min_volume))?;
+        tiers.push(FeeTier { min_volume: Amount(min_volume), maker_bps: tier.maker_bps, taker_bps: tier.taker_bps });
+    }
+    tiers.sort_by_key(|tier| tier.min_volume);
+
+    for (i, tier) in tiers.iter().enumerate() {
+        if i > 0 && tier.min_volume == tiers[i - 1].min_volume {
+            return Err(format!("two fee tiers start at volume {}", format_units(tier.min_volume.0 as u128, ASSET_DECIMALS)).into());
+        }
+        if !(0..=MAX_FEE_BPS).contains(&tier.taker_bps) || !(-MAX_FEE_BPS..=MAX_FEE_BPS).contains(&tier.maker_bps) {
+            return Err(format!("fee rates must be within ±{} bps and taker fees not negative", MAX_FEE_BPS).into());
+        }
+    }
+    let largest_rebate = tiers.iter().map(|tier| -tier.maker_bps).max().unwrap_or_default();
+    let lowest_taker_fee = tiers.iter().map(|tier| tier.taker_bps).min().unwrap_or_default();
+    if largest_rebate > lowest_taker_fee {
+        return Err(format!("a maker rebate of {} bps exceeds the lowest taker fee of {} bps", largest_rebate, lowest_taker_fee).into());
+    }
+    Ok(tiers)
+}
+
+const MAX_FEE_BPS: i32 = 10_000;
+
+/// How far back the volume that decides a user's fee tier reaches.
+const FEE_VOLUME_DAYS: i64 = 30;
+const NANOS_PER_DAY: i64 = 86_400 * crate::clock::NANOS_PER_SEC;
+
+impl Fee {
+    /// `bps` of `received`, rounded in the exchange's favour: fees up, rebates down.
+    fn charge(asset: &str, bps: i32, received: Amount) -> Self {
+        let scaled = received.0 as i128 * bps.unsigned_abs() as i128;
+        let amount = if bps >= 0 { (scaled + 9_999) / 10_000 } else { -(scaled / 10_000) };
+        Fee { asset: asset.to_string(), bps, amount: amount as i64 }
+    }
+}
+
+impl InstrumentSpec {
+    /// Parses a client price and checks it sits on the tick grid.
+    pub fn parse_price(&self, price: &Decimal) -> Result<Price, OrderError> {
+        let units = price.to_units(self.price_decimals).ok_or_else(|| {
+            OrderError::InvalidPrice(format!("{} is not a positive price with at most {} decimals", price, self.price_decimals))
+        })?;
+        if units == 0 {
+            return Err(OrderError::InvalidPrice("price must be greater than zero".to_string()));
+        }
+        if units % self.tick_size.0 != 0 {
+            return Err(OrderError::PriceOffTick { tick_size: self.format_price(self.tick_size) });
+        }
+        Ok(Price(units))
+    }
+
+    /// Parses a client quantity and checks it against lot size and order size limits.
+    pub fn parse_quantity(&self, quantity: &Decimal) -> Result<Quantity, OrderError> {
+        let units = quantity.to_units(self.quantity_decimals).ok_or_else(|| {
+            OrderError::InvalidQuantity(format!("{} is not a positive quantity with at most {} decimals", quantity, self.quantity_decimals))
+        })?;
+        let qty = Quantity(units);
+        if units % self.lot_size.0 != 0 {
+            return Err(OrderError::QuantityOffLot { lot_size: self.format_quantity(self.lot_size) });
+        }
+        if qty < self.min_quantity || qty.is_zero() {
+            return Err(OrderError::QuantityBelowMin { min_quantity: self.format_quantity(self.min_quantity) });
+        }
+        if qty > self.max_quantity {
+            return Err(OrderError::QuantityAboveMax { max_quantity: self.format_quantity(self.max_quantity) });
+        }
+        Ok(qty)
+    }
+
+    pub fn format_price(&self, price: Price) -> String {
+        format_units(price.0 as u128, self.price_decimals)
+    }
+
+    pub fn format_quantity(&self, qty: Quantity) -> String {
+        format_units(qty.0 as u128, self.quantity_decimals)
+    }
+
+    /// Exact `price * qty` in quote currency.
+    pub fn format_notional(&self, price: Price, qty: Quantity) -> String {
+        format_units(price.0 as u128 * qty.0 as u128, self.price_decimals + self.quantity_decimals)
+    }
+
+    /// `qty` of the base asset as a balance amount.
+    pub fn base_amount(&self, qty: Quantity) -> Amount {
+        let scale = 10u128.pow(ASSET_DECIMALS - self.quantity_decimals);
+        Amount(u64::try_from(qty.0 as u128 * scale).unwrap_or(u64::MAX))
+    }
+
+    /// Exact `price * qty` of the quote asset as a balance amount.
+    pub fn quote_amount(&self, price: Price, qty: Quantity) -> Amount {
+        let scale = 10u128.pow(ASSET_DECIMALS - self.price_decimals - self.quantity_decimals);
+        Amount(u64::try_from(price.0 as u128 * qty.0 as u128 * scale).unwrap_or(u64::MAX))
+    }
+
+    /// Most whole lots `budget` pays for at `price`.
+    pub fn affordable_quantity(&self, price: Price, budget: Amount) -> Quantity {
+        let unit_cost = self.quote_amount(price, Quantity(1)).0;
+        if unit_cost == 0 {
+            return Quantity(u64::MAX);
+        }
+        let units = budget.0 / unit_cost;
+        Quantity(units - units % self.lot_size.0)
+    }
+}
+
+impl Accounts {
+    /// Starting balances from `config.yaml`, each recorded in the ledger as an
+    /// opening deposit.
+    pub fn from_config(configs: &[AccountConfig]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut accounts = Accounts::default();
+        for config in configs {
+            for (asset, amount) in &config.balances {
+                let amount = amount
+                    .to_units(ASSET_DECIMALS)
+                    .ok_or_else(|| format!("{} balance of user {} must be a non-negative amount, got {}", asset, config.user_id, amount))?;
+                accounts.deposit(config.user_id, asset, Amount(amount), 0, format!("opening_balance:{}", config.user_id))?;
+            }
+        }
+        Ok(accounts)
+    }
+
+    pub fn balance(&self, user_id: u32, asset: &str) -> Balance {
+        self.balances
+            .get(&user_id)
+            .and_then(|assets| assets.get(asset))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn balance_mut(&mut self, user_id: u32, asset: &str) -> &mut Balance {
+        self.balances.entry(user_id).or_default().entry(asset.to_string()).or_default()
+    }
+
+    /// Every asset the user has held, by name.
+    pub fn user_balances(&self, user_id: u32) -> BTreeMap<String, Balance> {
+        self.balances.get(&user_id).cloned().unwrap_or_default()
+    }
+
+    /// Everything users hold of `asset`, available or locked, plus the fees
+    /// collected in it and not yet paid out as rebates.
+    pub fn supply(&self, asset: &str) -> u128 {
+        let held: u128 = self
+            .balances
+            .values()
+            .filter_map(|assets| assets.get(asset))
+            .map(|balance| balance.available.0 as u128 + balance.locked.0 as u128)
+            .sum();
+        held + self.fee_pool.get(asset).map_or(0, |pool| pool.0 as u128)
+    }
+
+    /// Rejects a deposit that would take the asset's supply past what a
+    /// balance can hold. Fills only move funds between users and the fee pool,
+    /// and a rebate is paid out of the same fill's taker fee, so keeping the
+    /// supply in range keeps every credit in range too. Only a fill that
+    /// overdraws its payer adds funds, and `credit` caps what that adds.
+    pub fn check_deposit(&self, asset: &str, amount: Amount) -> Result<(), OrderError> {
+        if self.supply(asset) + amount.0 as u128 > u64::MAX as u128 {
+            return Err(OrderError::InvalidAmount(format!(
+                "{} {} would take the total held above {}",
+                format_units(amount.0 as u128, ASSET_DECIMALS),
+                asset,
+                format_units(u64::MAX as u128, ASSET_DECIMALS)
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn deposit(&mut self, user_id: u32, asset: &str, amount: Amount, timestamp: i64, reference: String) -> Result<Balance, OrderError> {
+        self.check_deposit(asset, amount)?;
+        let mut postings = Vec::new();
+        transfer(&mut postings, LedgerAccount::External, LedgerAccount::User(user_id), asset, amount);
+        self.record(timestamp, reference, postings);
+
+        let balance = self.balance_mut(user_id, asset);
+        balance.available += amount;
+        Ok(*balance)
+    }
+
+    /// Takes `amount` out of the available balance. Funds locked for open
+    /// orders cannot be withdrawn.
+    pub fn withdraw(&mut self, user_id: u32, asset: &str, amount: Amount, timestamp: i64, reference: String) -> Result<Balance, OrderError> {
+        let balance = self.balance(user_id, asset);
+        if balance.available < amount {
+            return Err(OrderError::InsufficientFunds {
+                asset: asset.to_string(),
+                required: format_units(amount.0 as u128, ASSET_DECIMALS),
+                available: format_units(balance.available.0 as u128, ASSET_DECIMALS),
+            });
+        }
+        let mut postings = Vec::new();
+        transfer(&mut postings, LedgerAccount::User(user_id), LedgerAccount::External, asset, amount);
+        self.record(timestamp, reference, postings);
+
+        let balance = self.balance_mut(user_id, asset);
+        balance.available -= amount;
+        Ok(*balance)
+    }
+
+    /// Reserves `amount` of `asset` for an order, or rejects it when the user
+    /// has less than that available.
+    pub fn lock(&mut self, order_id: OrderId, user_id: u32, asset: &str, amount: Amount) -> Result<(), OrderError> {
+        let balance = self.balance_mut(user_id, asset);
+        if balance.available < amount {
+            return Err(OrderError::InsufficientFunds {
+                asset: asset.to_string(),
+                required: format_units(amount.0 as u128, ASSET_DECIMALS),
+                available: format_units(balance.available.0 as u128, ASSET_DECIMALS),
+            });
+        }
+        balance.available -= amount;
+        balance.locked = balance.locked.checked_add(amount).expect("balance within the asset supply");
+        let lock = self.order_locks.entry(order_id).or_insert(OrderLock {
+            user_id,
+            asset: asset.to_string(),
+            amount: Amount::ZERO,
+        });
+        lock.amount += amount;
+        Ok(())
+    }
+
+    /// Changes what an open order reserves to `amount`, rejecting an increase
+    /// the user cannot cover.
+    pub fn relock(&mut self, order_id: OrderId, user_id: u32, asset: &str, amount: Amount) -> Result<(), OrderError> {
+        let current = self.order_locks.get(&order_id).map(|lock| lock.amount).unwrap_or_default();
+        if amount > current {
+            return self.lock(order_id, user_id, asset, amount - current);
+        }
+        self.trim_lock(order_id, amount);
+        Ok(())
+    }
+
+    /// Returns whatever an open order reserves beyond `amount`.
+    pub fn trim_lock(&mut self, order_id: OrderId, amount: Amount) {
+        let Some(lock) = self.order_locks.get_mut(&order_id).filter(|lock| lock.amount > amount) else {
+            return;
+        };
+        let excess = lock.amount - amount;
+        lock.amount = amount;
+        let (user_id, asset) = (lock.user_id, lock.asset.clone());
+        let balance = self.balance_mut(user_id, &asset);
+        balance.locked = balance.locked.saturating_sub(excess);
+        balance.available = balance.available.checked_add(excess).expect("balance within the asset supply");
+    }
+
+    /// Returns whatever is still reserved for an order that has left the book.
+    pub fn release(&mut self, order_id: OrderId) {
+        if let Some(lock) = self.order_locks.remove(&order_id) {
+            let balance = self.balance_mut(lock.user_id, &lock.asset);
+            balance.locked = balance.locked.saturating_sub(lock.amount);
+            balance.available = balance.available.checked_add(lock.amount).expect("balance within the asset supply");
+        }
+    }
+
+    /// Pays `amount` out of an order's reservation, then out of available
+    /// funds. Whatever neither covers is recorded as an overdraft, which
+    /// `validate` reports, rather than failing a fill that has already happened.
+    fn spend(&mut self, order_id: OrderId, user_id: u32, asset: &str, amount: Amount) {
+        let from_lock = match self.order_locks.get_mut(&order_id) {
+            Some(lock) if lock.asset == asset => {
+                let spent = lock.amount.min(amount);
+                lock.amount -= spent;
+                spent
+            }
+            _ => Amount::ZERO,
+        };
+        let balance = self.balance_mut(user_id, asset);
+        balance.locked = balance.locked.saturating_sub(from_lock);
+        let from_available = amount - from_lock;
+        let shortfall = from_available.saturating_sub(balance.available);
+        balance.available = balance.available.saturating_sub(from_available);
+        if !shortfall.is_zero() {
+            *self.overdrafts.entry(user_id).or_default().entry(asset.to_string()).or_default() += shortfall;
+        }
+    }
+
+    /// Adds `amount` to the available balance. Funds an overdraft conjured may
+    /// not fit the balance; anything past what it can hold is dropped, and the
+    /// overdraft is left for `validate` to report.
+    fn credit(&mut self, user_id: u32, asset: &str, amount: Amount) {
+        let balance = self.balance_mut(user_id, asset);
+        let room = Amount(u64::MAX - balance.available.0 - balance.locked.0);
+        balance.available += amount.min(room);
+    }
+
+    /// Moves one fill's base and quote between buyer and seller.
+    /// Fees are taken from what each side receives (base for the buyer, quote
+    /// for the seller) at the rates of the tier its trailing volume reaches
+    /// before this fill, and are filled in on the trade. A maker rebate is paid
+    /// in the asset the taker received, so the taker fee always covers it.
+    pub fn settle(&mut self, trade: &mut Trade, instrument: &Instrument) {
+        let (buy_order, buyer, sell_order, seller) = match trade.aggressor_side {
+            Side::Buy => (trade.taker_order_id, trade.taker_user_id, trade.maker_order_id, trade.maker_user_id),
+            Side::Sell => (trade.maker_order_id, trade.maker_user_id, trade.taker_order_id, trade.taker_user_id),
+        };
+        let cost = instrument.spec.quote_amount(trade.price, trade.qty);
+        let base = instrument.spec.base_amount(trade.qty);
+
+        let quote = &instrument.quote_asset;
+        let maker_tier = instrument.fee_tier(self.trailing_volume(trade.maker_user_id, quote, trade.time));
+        let taker_tier = instrument.fee_tier(self.trailing_volume(trade.taker_user_id, quote, trade.time));
+        let (maker_receives, taker_receives) = match trade.aggressor_side {
+            Side::Buy => ((quote, cost), (&instrument.base_asset, base)),
+            Side::Sell => ((&instrument.base_asset, base), (quote, cost)),
+        };
+        // Rebates are rounded down and taker fees up on the same amount, so the
+        // taker fee funds the rebate whatever tiers the two sides are in
+        let maker_charged_on = if maker_tier.maker_bps < 0 { taker_receives } else { maker_receives };
+        trade.maker_fee = Fee::charge(maker_charged_on.0, maker_tier.maker_bps, maker_charged_on.1);
+        trade.taker_fee = Fee::charge(taker_receives.0, taker_tier.taker_bps, taker_receives.1);
+
+        let mut postings = Vec::new();
+        transfer(&mut postings, LedgerAccount::User(buyer), LedgerAccount::User(seller), quote, cost);
+        transfer(&mut postings, LedgerAccount::User(seller), LedgerAccount::User(buyer), &instrument.base_asset, base);
+        for (user_id, fee) in [(trade.maker_user_id, &trade.maker_fee), (trade.taker_user_id, &trade.taker_fee)] {
+            let amount = Amount(fee.amount.unsigned_abs());
+            match fee.amount.signum() {
+                1 => transfer(&mut postings, LedgerAccount::User(user_id), LedgerAccount::Fees, &fee.asset, amount),
+                -1 => transfer(&mut postings, LedgerAccount::Fees, LedgerAccount::User(user_id), &fee.asset, amount),
+                _ => {}
+            }
+        }
+        self.record(trade.time, format!("trade:{}:{}", trade.symbol, trade.trade_id), postings);
+
+        self.spend(buy_order, buyer, quote, cost);
+        self.credit(buyer, &instrument.base_asset, base);
+        self.spend(sell_order, seller, &instrument.base_asset, base);
+        self.credit(seller, quote, cost);
+        // The taker fee reaches the pool first, so it is there to pay the maker's rebate
+        for (order_id, user_id, fee) in [
+            (trade.taker_order_id, trade.taker_user_id, &trade.taker_fee),
+            (trade.maker_order_id, trade.maker_user_id, &trade.maker_fee),
+        ] {
+            let amount = Amount(fee.amount.unsigned_abs());
+            let pool = self.fee_pool.entry(fee.asset.clone()).or_default();
+            if fee.amount >= 0 {
+                *pool = pool.saturating_add(amount);
+                // Fees come out of what the user just received, never what the order reserved
+                self.spend(order_id, user_id, &fee.asset, amount);
+            } else {
+                *pool = pool.saturating_sub(amount);
+                self.credit(user_id, &fee.asset, amount);
+            }
+        }
+
+        self.record_volume(trade.maker_user_id, quote, cost, trade.time);
+        self.record_volume(trade.taker_user_id, quote, cost, trade.time);
+    }
+
+    /// Notional in `asset` the user traded over the trailing 30 days as of `now` (unix nanoseconds).
+    pub fn trailing_volume(&self, user_id: u32, asset: &str, now: i64) -> Amount {
+        let today = now.div_euclid(NANOS_PER_DAY);
+        self.volumes
+            .get(&user_id)
+            .and_then(|assets| assets.get(asset))
+            .map(|days| days.range(today - FEE_VOLUME_DAYS + 1..=today).map(|(_, volume)| *volume).sum())
+            .unwrap_or_default()
+    }
+
+    fn record_volume(&mut self, user_id: u32, asset: &str, amount: Amount, now: i64) {
+        let today = now.div_euclid(NANOS_PER_DAY);
+        let days = self.volumes.entry(user_id).or_default().entry(asset.to_string()).or_default();
+        *days.entry(today).or_default() += amount;
+        days.retain(|day, _| *day > today - FEE_VOLUME_DAYS);
+    }
+
+    /// Takes the ledger entries recorded since the last drain.
+    pub fn drain_ledger_entries(&mut self) -> Vec<LedgerEntry> {
+        std::mem::take(&mut self.ledger_entries)
+    }
+
+    fn record(&mut self, timestamp: i64, reference: String, postings: Vec<Posting>) {
+        self.ledger_entries.push(LedgerEntry { seq: self.next_ledger_seq, timestamp, reference, postings });
+        self.next_ledger_seq += 1;
+    }
+
+    /// Locked totals that disagree with the sum of the order reservations,
+    /// and any overdrafts.
+    pub fn validate(&self) -> Vec<String> {
+        let mut reserved: BTreeMap<(u32, &str), Amount> = BTreeMap::new();
+        for lock in self.order_locks.values() {
+            *reserved.entry((lock.user_id, &lock.asset)).or_default() += lock.amount;
+        }
+
+        let mut problems = Vec::new();
+        for (user_id, assets) in &self.balances {
+            for (asset, balance) in assets {
+                let expected = reserved.remove(&(*user_id, asset.as_str())).unwrap_or_default();
+                if balance.locked != expected {
+                    problems.push(format!(
+                        "user {} {} locked {} but open orders reserve {}",
+                        user_id, asset, balance.locked.0, expected.0
+                    ));
+                }
+            }
+        }
+        for ((user_id, asset), amount) in reserved {
+            problems.push(format!("user {} has {} {} reserved but no {} balance", user_id, amount.0, asset, asset));
+        }
+        for (user_id, assets) in &self.overdrafts {
+            for (asset, amount) in assets {
+                problems.push(format!("user {} was overdrawn by {} {}", user_id, amount.0, asset));
+            }
+        }
+        problems
+    }
+}
+
+/// Debits `from` and credits `to` with `amount` of `asset`.
+fn transfer(postings: &mut Vec<Posting>, from: LedgerAccount, to: LedgerAccount, asset: &str, amount: Amount) {
+    postings.push(Posting { account: from, asset: asset.to_string(), side: EntrySide::Debit, amount });
+    postings.push(Posting { account: to, asset: asset.to_string(), side: EntrySide::Credit, amount });
+}
+
+impl PriceLevel {
+    /// Appends an order to the back of the queue and returns its position.
+    pub fn push(&mut self, order: Order) -> u64 {
+        self.orders.push_back(Some(order));
+        self.head + self.orders.len() as u64 - 1
+    }
+
+    pub fn get(&self, position: u64) -> Option<&Order> {
+        let i = position.checked_sub(self.head)?;
+        self.orders.get(i as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, position: u64) -> Option<&mut Order> {
+        let i = position.checked_sub(self.head)?;
+        self.orders.get_mut(i as usize)?.as_mut()
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut Order> {
+        self.orders.front_mut()?.as_mut()
+    }
+
+    pub fn pop_front(&mut self) -> Option<Order> {
+        let order = self.orders.pop_front()?;
+        self.head += 1;
+        self.trim();
+        order
+    }
+
+    pub fn remove(&mut self, position: u64) -> Option<Order> {
+        let i = position.checked_sub(self.head)?;
+        let order = self.orders.get_mut(i as usize)?.take();
+        if order.is_some() {
+            self.tombstones += 1;
+        }
+        self.trim();
+        order
+    }
+
+    /// Drops the removed slots once they make up half the queue, keeping the
+    /// live orders in time order. Returns the orders that moved with their new
+    /// positions, for the caller to update its index; empty when the level was
+    /// left as it was.
+    pub fn compact(&mut self) -> Vec<(OrderId, u64)> {
+        if self.tombstones * 2 <= self.orders.len() {
+            return Vec::new();
+        }
+        self.orders.retain(Option::is_some);
+        self.tombstones = 0;
+        (self.head..).zip(self.iter()).map(|(position, order)| (order.id, position)).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.orders.iter().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    // Drops removed slots from both ends so the front is always a live order.
+    fn trim(&mut self) {
+        while let Some(None) = self.orders.front() {
+            self.orders.pop_front();
+            self.head += 1;
+            self.tombstones -= 1;
+        }
+        while let Some(None) = self.orders.back() {
+            self.orders.pop_back();
+            self.tombstones -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::{NOW, fee_instrument, fill, instrument};
+    use crate::inputs::FeeScheduleConfig;
+
+    const USD: u64 = 100_000_000;
+
+    fn tiered() -> Instrument {
+        fee_instrument(FeeScheduleConfig {
+            maker_bps: 0,
+            taker_bps: 10,
+            tiers: vec![
+                FeeTierConfig { min_volume: Decimal::from("1000"), maker_bps: -3, taker_bps: 6 },
+                FeeTierConfig { min_volume: Decimal::from("5000"), maker_bps: -4, taker_bps: 4 },
+            ],
+        })
+    }
+
+    fn funded() -> Accounts {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, "BTC", Amount(20 * USD), NOW, "deposit:1".to_string()).unwrap();
+        for user_id in [2, 3] {
+            accounts.deposit(user_id, "USD", Amount(10_000 * USD), NOW, format!("deposit:{}", user_id)).unwrap();
+        }
+        accounts
+    }
+
+    #[test]
+    fn fees_round_up_and_rebates_round_down() {
+        assert_eq!(Fee::charge("USD", 10, Amount(12_345)).amount, 13);
+        assert_eq!(Fee::charge("USD", 10, Amount(20_000)).amount, 20);
+        assert_eq!(Fee::charge("USD", -5, Amount(12_345)).amount, -6);
+        assert_eq!(Fee::charge("USD", 0, Amount(12_345)).amount, 0);
+    }
+
+    #[test]
+    fn fee_tier_is_the_highest_the_volume_reaches() {
+        let instrument = tiered();
+        let rates = |volume: u64| {
+            let tier = instrument.fee_tier(Amount(volume * USD));
+            (tier.maker_bps, tier.taker_bps)
+        };
+        assert_eq!(rates(0), (0, 10));
+        assert_eq!(rates(999), (0, 10));
+        assert_eq!(rates(1_000), (-3, 6));
+        assert_eq!(rates(7_500), (-4, 4));
+    }
+
+    #[test]
+    fn settle_charges_each_side_at_the_tier_its_volume_reached_before_the_fill() {
+        let instrument = tiered();
+        let mut accounts = funded();
+        // 10 BTC at 100 takes user 1 to the first tier; user 2 has no volume
+        accounts.settle(&mut fill(1, 3, 10_000, 10_000, NOW), &instrument);
+
+        let mut trade = fill(1, 2, 10_000, 1_000, NOW);
+        accounts.settle(&mut trade, &instrument);
+        // 10 bps on the taker's 1 BTC, 3 bps of which is rebated to the maker in BTC
+        assert_eq!((trade.maker_fee.asset.as_str(), trade.maker_fee.amount), ("BTC", -30_000));
+        assert_eq!((trade.taker_fee.asset.as_str(), trade.taker_fee.amount), ("BTC", 100_000));
+        assert_eq!(accounts.balance(1, "USD").available, Amount(1_100 * USD));
+        assert_eq!(accounts.balance(1, "BTC").available, Amount(9 * USD + 30_000));
+        assert_eq!(accounts.balance(2, "BTC").available, Amount(USD - 100_000));
+        assert_eq!(accounts.balance(2, "USD").available, Amount(9_900 * USD));
+        assert!(accounts.validate().is_empty());
+    }
+
+    #[test]
+    fn a_rebate_at_the_supply_cap_is_paid_from_the_fee_pool() {
+        let instrument = fee_instrument(FeeScheduleConfig { maker_bps: -2, taker_bps: 5, tiers: vec![] });
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, "BTC", Amount(u64::MAX - USD), NOW, "deposit:1".to_string()).unwrap();
+        accounts.deposit(2, "BTC", Amount(USD), NOW, "deposit:2".to_string()).unwrap();
+        accounts.deposit(2, "USD", Amount(100 * USD), NOW, "deposit:2".to_string()).unwrap();
+
+        let mut trade = fill(1, 2, 10_000, 1_000, NOW);
+        accounts.settle(&mut trade, &instrument);
+        assert_eq!(trade.maker_fee.amount, -20_000);
+        assert_eq!(accounts.balance(1, "BTC").available, Amount(u64::MAX - 2 * USD + 20_000));
+        assert_eq!(accounts.fee_pool["BTC"], Amount(30_000));
+        assert_eq!(accounts.supply("BTC"), u64::MAX as u128);
+        assert!(accounts.check_deposit("BTC", Amount(1)).is_err());
+        assert!(accounts.validate().is_empty());
+    }
+
+    #[test]
+    fn trailing_volume_covers_thirty_days() {
+        let mut accounts = funded();
+        accounts.settle(&mut fill(1, 2, 10_000, 1_000, NOW), &tiered());
+        assert_eq!(accounts.trailing_volume(1, "USD", NOW + 29 * NANOS_PER_DAY), Amount(100 * USD));
+        assert_eq!(accounts.trailing_volume(1, "USD", NOW + 30 * NANOS_PER_DAY), Amount::ZERO);
+    }
+
+    #[test]
+    fn a_fill_beyond_what_the_seller_holds_is_recorded_as_an_overdraft() {
+        let mut accounts = funded();
+        // User 1 holds 20 BTC and nothing reserved for order 1
+        accounts.settle(&mut fill(1, 2, 10_000, 25_000, NOW), &instrument());
+        assert_eq!(accounts.balance(1, "BTC").available, Amount::ZERO);
+        assert_eq!(accounts.overdrafts[&1]["BTC"], Amount(5 * USD));
+        assert!(accounts.validate().contains(&format!("user 1 was overdrawn by {} BTC", 5 * USD)));
+    }
+
+    #[test]
+    fn an_overdraft_cannot_credit_a_balance_past_what_it_holds() {
+        let mut accounts = Accounts::default();
+        accounts.deposit(2, "BTC", Amount(u64::MAX), NOW, "deposit:2".to_string()).unwrap();
+        accounts.deposit(2, "USD", Amount(100 * USD), NOW, "deposit:2".to_string()).unwrap();
+
+        // User 1 sells a BTC it does not have to the holder of the whole supply
+        accounts.settle(&mut fill(1, 2, 10_000, 1_000, NOW), &instrument());
+        assert_eq!(accounts.balance(2, "BTC").available, Amount(u64::MAX));
+        assert_eq!(accounts.balance(1, "USD").available, Amount(100 * USD));
+        assert_eq!(accounts.validate(), vec![format!("user 1 was overdrawn by {} BTC", USD)]);
+    }
+}
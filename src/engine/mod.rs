@@ -1,2 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod engine;
-pub mod service;
\ No newline at end of file
+pub mod risk;
+pub mod service;
+#[cfg(test)]
+pub(crate) mod testing;
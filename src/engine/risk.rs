@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::OrderError,
+    inputs::{Accounts, Amount, CollarReference, Decimal, Order, OrderBook, OrderType, Price, RiskConfig, RiskLimitsConfig, Symbol, UserOrders},
+    units::{ASSET_DECIMALS, format_units},
+};
+
+/// Limits that apply to one order, with decimal amounts converted to balance units.
+#[derive(Debug, Clone, Default)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<Amount>,
+    pub max_order_notional: Option<Amount>,
+    pub price_collar_bps: Option<u32>,
+    pub collar_reference: Option<CollarReference>,
+    pub max_open_orders: Option<usize>,
+    pub max_position: Option<Amount>,
+}
+
+impl RiskLimits {
+    fn from_config(config: &RiskLimitsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let amount = |value: &Option<Decimal>, name: &str| -> Result<Option<Amount>, String> {
+            value
+                .as_ref()
+                .map(|value| {
+                    value
+                        .to_units(ASSET_DECIMALS)
+                        .map(Amount)
+                        .ok_or_else(|| format!("{} must be a non-negative amount, got {}", name, value))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            max_order_quantity: amount(&config.max_order_quantity, "max_order_quantity")?,
+            max_order_notional: amount(&config.max_order_notional, "max_order_notional")?,
+            price_collar_bps: config.price_collar_bps,
+            collar_reference: config.collar_reference,
+            max_open_orders: config.max_open_orders,
+            max_position: amount(&config.max_position, "max_position")?,
+        })
+    }
+
+    /// Replaces every limit `other` sets.
+    fn overlay(&mut self, other: &RiskLimits) {
+        self.max_order_quantity = other.max_order_quantity.or(self.max_order_quantity);
+        self.max_order_notional = other.max_order_notional.or(self.max_order_notional);
+        self.price_collar_bps = other.price_collar_bps.or(self.price_collar_bps);
+        self.collar_reference = other.collar_reference.or(self.collar_reference);
+        self.max_open_orders = other.max_open_orders.or(self.max_open_orders);
+        self.max_position = other.max_position.or(self.max_position);
+    }
+}
+
+/// What a check sees: the order as it would enter the book, the book before
+/// it does and the balances behind it. An amendment also names the order it
+/// `replaces`, which is still in the book.
+pub struct RiskContext<'a> {
+    pub order: &'a Order,
+    pub orderbook: &'a OrderBook,
+    pub accounts: &'a Accounts,
+    pub limits: &'a RiskLimits,
+    pub replaces: Option<&'a Order>,
+}
+
+impl RiskContext<'_> {
+    /// The user's open orders in the book, less the one being replaced.
+    pub fn open_orders(&self) -> UserOrders {
+        let mut open = self.orderbook.user_orders.get(&self.order.user_id).copied().unwrap_or_default();
+        if let Some(replaced) = self.replaces {
+            open.open = open.open.saturating_sub(1);
+            if replaced.is_buy {
+                open.buy_qty = open.buy_qty.saturating_sub(replaced.total_qty());
+            }
+        }
+        open
+    }
+}
+
+/// One pre-trade check. A check whose limit is unset passes.
+pub trait RiskCheck: Send + Sync {
+    fn check(&self, context: &RiskContext) -> Result<(), OrderError>;
+}
+
+/// Runs every check against an order before it reaches its book, with limits
+/// from the `risk` section of `config.yaml`.
+pub struct RiskEngine {
+    defaults: RiskLimits,
+    symbols: HashMap<Symbol, RiskLimits>,
+    users: HashMap<u32, RiskLimits>,
+    user_symbols: HashMap<(u32, Symbol), RiskLimits>,
+    checks: Vec<Box<dyn RiskCheck>>,
+}
+
+impl RiskEngine {
+    /// The configured limits with the built-in checks.
+    pub fn from_config(config: &RiskConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut engine = RiskEngine {
+            defaults: RiskLimits::from_config(&config.defaults)?,
+            symbols: HashMap::new(),
+            users: HashMap::new(),
+            user_symbols: HashMap::new(),
+            checks: vec![
+                Box::new(MaxOrderQuantity),
+                Box::new(MaxOrderNotional),
+                Box::new(PriceCollar),
+                Box::new(MaxOpenOrders),
+                Box::new(MaxPosition),
+            ],
+        };
+        for entry in &config.symbols {
+            let limits = RiskLimits::from_config(&entry.limits).map_err(|e| format!("{}: {}", entry.symbol, e))?;
+            engine.symbols.insert(entry.symbol.clone(), limits);
+        }
+        for entry in &config.users {
+            let limits = RiskLimits::from_config(&entry.limits).map_err(|e| format!("user {}: {}", entry.user_id, e))?;
+            match &entry.symbol {
+                Some(symbol) => engine.user_symbols.insert((entry.user_id, symbol.clone()), limits),
+                None => engine.users.insert(entry.user_id, limits),
+            };
+        }
+        Ok(engine)
+    }
+
+    /// Adds a check that runs after those already registered.
+    pub fn add_check(&mut self, check: Box<dyn RiskCheck>) {
+        self.checks.push(check);
+    }
+
+    /// Limits for `user_id` trading `symbol`, most specific entry winning.
+    pub fn limits(&self, symbol: &Symbol, user_id: u32) -> RiskLimits {
+        let mut limits = self.defaults.clone();
+        for layer in [
+            self.symbols.get(symbol),
+            self.users.get(&user_id),
+            self.user_symbols.get(&(user_id, symbol.clone())),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            limits.overlay(layer);
+        }
+        limits
+    }
+
+    /// The first check the order fails, if any. An amendment passes the open
+    /// order it `replaces`, so its current size does not count twice.
+    pub fn check(&self, order: &Order, orderbook: &OrderBook, accounts: &Accounts, replaces: Option<&Order>) -> Result<(), OrderError> {
+        let limits = self.limits(&orderbook.instrument.symbol, order.user_id);
+        let context = RiskContext { order, orderbook, accounts, limits: &limits, replaces };
+        self.checks.iter().try_for_each(|check| check.check(&context))
+    }
+}
+
+pub struct MaxOrderQuantity;
+
+impl RiskCheck for MaxOrderQuantity {
+    fn check(&self, context: &RiskContext) -> Result<(), OrderError> {
+        let Some(limit) = context.limits.max_order_quantity else {
+            return Ok(());
+        };
+        if context.orderbook.instrument.spec.base_amount(context.order.qty) > limit {
+            return Err(OrderError::RiskMaxQuantity { limit: format_amount(limit) });
+        }
+        Ok(())
+    }
+}
+
+/// Market orders are valued at the touch, stop orders at their stop price.
+pub struct MaxOrderNotional;
+
+impl RiskCheck for MaxOrderNotional {
+    fn check(&self, context: &RiskContext) -> Result<(), OrderError> {
+        let Some(limit) = context.limits.max_order_notional else {
+            return Ok(());
+        };
+        let Some(price) = expected_price(context.order, context.orderbook) else {
+            return Ok(());
+        };
+        let notional = context.orderbook.instrument.spec.quote_amount(price, context.order.qty);
+        if notional > limit {
+            return Err(OrderError::RiskMaxNotional { notional: format_amount(notional), limit: format_amount(limit) });
+        }
+        Ok(())
+    }
+}
+
+/// Applies to orders with a limit price; passes while the book has no reference price.
+pub struct PriceCollar;
+
+impl RiskCheck for PriceCollar {
+    fn check(&self, context: &RiskContext) -> Result<(), OrderError> {
+        let (Some(collar_bps), OrderType::Limit | OrderType::StopLimit) = (context.limits.price_collar_bps, context.order.order_type) else {
+            return Ok(());
+        };
+        let orderbook = context.orderbook;
+        let mid = orderbook
+            .current_best_bid
+            .zip(orderbook.current_best_ask)
+            .map(|(bid, ask)| Price((bid.0 + ask.0) / 2));
+        let last = orderbook.last_trade_price;
+        let (name, reference) = match context.limits.collar_reference.unwrap_or_default() {
+            CollarReference::LastTrade => match (last, mid) {
+                (Some(last), _) => ("last trade price", last),
+                (None, Some(mid)) => ("mid price", mid),
+                (None, None) => return Ok(()),
+            },
+            CollarReference::Mid => match (mid, last) {
+                (Some(mid), _) => ("mid price", mid),
+                (None, Some(last)) => ("last trade price", last),
+                (None, None) => return Ok(()),
+            },
+        };
+
+        let distance = context.order.price.0.abs_diff(reference.0) as u128;
+        if distance * 10_000 > collar_bps as u128 * reference.0 as u128 {
+            return Err(OrderError::RiskPriceCollar {
+                reference: format!("the {} {}", name, orderbook.instrument.spec.format_price(reference)),
+                collar_bps,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct MaxOpenOrders;
+
+impl RiskCheck for MaxOpenOrders {
+    fn check(&self, context: &RiskContext) -> Result<(), OrderError> {
+        let Some(limit) = context.limits.max_open_orders else {
+            return Ok(());
+        };
+        if context.open_orders().open >= limit {
+            return Err(OrderError::RiskMaxOpenOrders { limit });
+        }
+        Ok(())
+    }
+}
+
+/// Spot positions only grow through buys: the base asset the user holds plus
+/// everything their open buys in the book, this one included, would add.
+pub struct MaxPosition;
+
+impl RiskCheck for MaxPosition {
+    fn check(&self, context: &RiskContext) -> Result<(), OrderError> {
+        let (Some(limit), true) = (context.limits.max_position, context.order.is_buy) else {
+            return Ok(());
+        };
+        let instrument = &context.orderbook.instrument;
+        let held = context.accounts.balance(context.order.user_id, &instrument.base_asset);
+        let buying = context.open_orders().buy_qty + context.order.qty;
+        let position = held.available + held.locked + instrument.spec.base_amount(buying);
+        if position > limit {
+            return Err(OrderError::RiskMaxPosition { position: format_amount(position), limit: format_amount(limit) });
+        }
+        Ok(())
+    }
+}
+
+/// Price the order is expected to trade at.
+fn expected_price(order: &Order, orderbook: &OrderBook) -> Option<Price> {
+    match order.order_type {
+        OrderType::Limit | OrderType::StopLimit => Some(order.price),
+        OrderType::Stop => order.stop_price,
+        OrderType::Market => {
+            let touch = if order.is_buy { orderbook.current_best_ask } else { orderbook.current_best_bid };
+            touch.or(orderbook.last_trade_price)
+        }
+    }
+}
+
+fn format_amount(amount: Amount) -> String {
+    format_units(amount.0 as u128, ASSET_DECIMALS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::{book, limit};
+    use crate::inputs::Quantity;
+
+    fn engine(limits: RiskLimitsConfig) -> RiskEngine {
+        RiskEngine::from_config(&RiskConfig { defaults: limits, ..Default::default() }).unwrap()
+    }
+
+    fn code(result: Result<(), OrderError>) -> &'static str {
+        result.map_or_else(|e| e.code(), |_| "OK")
+    }
+
+    #[test]
+    fn open_orders_do_not_count_the_order_being_amended() {
+        let risk = engine(RiskLimitsConfig { max_open_orders: Some(2), ..Default::default() });
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, true, 100, 1_000));
+        orderbook.add_order(limit(2, false, 200, 1_000));
+        let accounts = Accounts::default();
+
+        assert_eq!(code(risk.check(&limit(3, true, 101, 1_000), &orderbook, &accounts, None)), "RISK_MAX_OPEN_ORDERS");
+        let resting = orderbook.get_order(1).cloned().unwrap();
+        let amended = Order { price: Price(101), ..resting.clone() };
+        assert_eq!(code(risk.check(&amended, &orderbook, &accounts, Some(&resting))), "OK");
+
+        orderbook.cancel_order(2);
+        assert_eq!(code(risk.check(&limit(3, true, 101, 1_000), &orderbook, &accounts, None)), "OK");
+    }
+
+    #[test]
+    fn position_counts_an_amended_buy_at_its_new_size_only() {
+        let risk = engine(RiskLimitsConfig { max_position: Some(Decimal::from("5")), ..Default::default() });
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, true, 100, 4_000));
+        let accounts = Accounts::default();
+        let resting = orderbook.get_order(1).cloned().unwrap();
+
+        let grown = Order { qty: Quantity(5_000), ..resting.clone() };
+        assert_eq!(code(risk.check(&grown, &orderbook, &accounts, None)), "RISK_MAX_POSITION");
+        assert_eq!(code(risk.check(&grown, &orderbook, &accounts, Some(&resting))), "OK");
+        let too_big = Order { qty: Quantity(6_000), ..resting.clone() };
+        assert_eq!(code(risk.check(&too_big, &orderbook, &accounts, Some(&resting))), "RISK_MAX_POSITION");
+    }
+}
@@ -1,117 +1,268 @@
-use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::clock::{Clock, NANOS_PER_SEC};
 use serde_json::Value;
 
 use crate::{
-    global::{NEXT_ORDER_ID, ORDERBOOKS}, 
-    inputs::{CreateOrderInput, Order, OrderBook, OrderBookState, OrderType, ProcessOrderResult, Side}
+    engine::risk::{RiskCheck, RiskEngine},
+    error::OrderError,
+    units::ASSET_DECIMALS,
+    global::{ACCOUNTS, NEXT_ORDER_ID, ORDERBOOKS, RISK}, 
+    inputs::{Accounts, Amount, Balance, BookDepth, BookOrders, Decimal, Fee, BookEvent, BookEventBatch, BookEventKind, CancelOrderInput, CancelOrderResult, CreateOrderInput, Instrument, InstrumentConfig, InstrumentStatus, InstrumentStatusInput, LevelChange, ModifyOrderInput, ModifyOrderResult, Order, OrderBook, OrderBookState, OrderId, OrderLocation, OrderStatus, OrderType, PostOnly, Price, PriceLevel, LedgerEntry, ProcessOrderResult, Quantity, TransferInput, TransferResult, SelfTradePrevented, SelfTradePrevention, Side, Symbol, TimeInForce, Trade, UserOrders}
 };
 
-pub fn add_order(orderbook: &mut OrderBook, order: Order) {
+pub fn add_order(orderbook: &mut OrderBook, mut order: Order) {
+    // Icebergs rest with at most one peak displayed
+    if let Some(peak) = order.display_qty
+        && order.qty > peak
+    {
+        order.hidden_qty += order.qty - peak;
+        order.qty = peak;
+    }
     let book = if order.is_buy {
         &mut orderbook.bids
     } else {
         &mut orderbook.asks
     };
-    book.entry(order.price).or_insert_with(Vec::new).push(order);
+    let (id, is_buy, price) = (order.id, order.is_buy, order.price);
+    if let Some(expires_at) = order.expires_at {
+        orderbook.expiries.insert((expires_at, id));
+    }
+    orderbook.book_events.push(BookEvent::new(BookEventKind::Add, &order, order.qty));
+    track_order(&mut orderbook.user_orders, &order);
+    let position = book.entry(price).or_default().push(order);
+    orderbook.order_index.insert(id, OrderLocation { is_buy, is_stop: false, price, position });
     update_best_prices(orderbook);
 }
 
-pub fn match_order(orderbook: &mut OrderBook, incoming_order: &Order, order_type: OrderType) -> Vec<Order> {
-    let mut trades:Vec<Order> = Vec::new();
-    let mut qty_left :u64= incoming_order.qty;
-    if incoming_order.is_buy {
-        let mut prices: Vec<u64> = orderbook.asks.keys().cloned().collect();
-        prices.sort();
-        
-        for price in prices {
+/// Parks a Stop/StopLimit order until the last trade price reaches its stop price.
+pub fn add_stop_order(orderbook: &mut OrderBook, order: Order) {
+    let stops = if order.is_buy {
+        &mut orderbook.buy_stops
+    } else {
+        &mut orderbook.sell_stops
+    };
+    let (id, is_buy) = (order.id, order.is_buy);
+    let price = order.stop_price.unwrap_or(order.price);
+    if let Some(expires_at) = order.expires_at {
+        orderbook.expiries.insert((expires_at, id));
+    }
+    track_order(&mut orderbook.user_orders, &order);
+    let position = stops.entry(price).or_default().push(order);
+    orderbook.order_index.insert(id, OrderLocation { is_buy, is_stop: true, price, position });
+}
+
+/// `now` (unix nanoseconds) stamps the trades and decides which resting orders
+/// have expired, so the same inputs always produce the same trades.
+pub fn match_order(orderbook: &mut OrderBook, incoming_order: &Order, order_type: OrderType, now: i64) -> Vec<Trade> {
+    expire_orders(orderbook, now.div_euclid(NANOS_PER_SEC));
+    let mut trades = execute_order(orderbook, incoming_order, order_type, now);
+    trades.extend(trigger_stop_orders(orderbook, now));
+    trades
+}
+
+/// Runs every stop order whose trigger has been reached, including stops
+/// triggered by the fills of earlier triggered stops. Buy stops are released
+/// lowest stop price first, then sell stops highest first, FIFO within a price.
+pub fn trigger_stop_orders(orderbook: &mut OrderBook, now: i64) -> Vec<Trade> {
+    let mut trades = Vec::new();
+    while let Some(mut order) = next_triggered_stop(orderbook) {
+        order.order_type = match order.order_type {
+            OrderType::StopLimit => OrderType::Limit,
+            _ => OrderType::Market,
+        };
+        trades.extend(execute_order(orderbook, &order, order.order_type, now));
+    }
+    trades
+}
+
+fn next_triggered_stop(orderbook: &mut OrderBook) -> Option<Order> {
+    let last_price = orderbook.last_trade_price?;
+    let (stops, price) = if let Some(&price) = orderbook.buy_stops.keys().next().filter(|p| **p <= last_price) {
+        (&mut orderbook.buy_stops, price)
+    } else if let Some(&price) = orderbook.sell_stops.keys().next_back().filter(|p| **p >= last_price) {
+        (&mut orderbook.sell_stops, price)
+    } else {
+        return None;
+    };
+
+    let level = stops.get_mut(&price)?;
+    let order = level.pop_front()?;
+    if level.is_empty() {
+        stops.remove(&price);
+    }
+    orderbook.order_index.remove(&order.id);
+    if let Some(expires_at) = order.expires_at {
+        orderbook.expiries.remove(&(expires_at, order.id));
+    }
+    untrack_order(&mut orderbook.user_orders, &order, order.total_qty(), true);
+    Some(order)
+}
+
+fn execute_order(orderbook: &mut OrderBook, incoming_order: &Order, order_type: OrderType, now: i64) -> Vec<Trade> {
+    let mut trades:Vec<Trade> = Vec::new();
+    let mut qty_left :Quantity= incoming_order.qty;
+    let mut budget_left = incoming_order.budget;
+    // Whatever part of the order does not rest has left the book
+    orderbook.closed_orders.push(incoming_order.id);
 
-            if order_type == OrderType::Limit && price > incoming_order.price {
+    let rests = matches!(
+        incoming_order.time_in_force,
+        TimeInForce::GTC | TimeInForce::GTD | TimeInForce::DAY
+    );
+
+    if incoming_order.post_only != PostOnly::Off
+        && order_type == OrderType::Limit
+        && let Some(touch) = best_opposite_price(orderbook, incoming_order.is_buy)
+        && crosses(incoming_order, touch)
+    {
+        if incoming_order.post_only == PostOnly::Reprice
+            && rests
+            && let Some(price) = repriced_price(orderbook, incoming_order.is_buy, touch)
+        {
+            add_order(orderbook, Order { price, ..incoming_order.clone() });
+        }
+        return trades;
+    }
+
+    if incoming_order.time_in_force == TimeInForce::FOK
+        && available_quantity(orderbook, incoming_order, order_type) < incoming_order.qty
+    {
+        return trades;
+    }
+
+    while !qty_left.is_zero() {
+        let Some(price) = best_opposite_price(orderbook, incoming_order.is_buy) else {
+            break;
+        };
+        if order_type == OrderType::Limit && !crosses(incoming_order, price) {
+            break;
+        }
+
+        let book = if incoming_order.is_buy {
+            &mut orderbook.asks
+        } else {
+            &mut orderbook.bids
+        };
+        let Some(resting_orders) = book.get_mut(&price) else {
+            break;
+        };
+
+        while !qty_left.is_zero() {
+            let Some(resting_order) = resting_orders.front_mut() else {
                 break;
-            }
-            if let Some(resting_orders) = orderbook.asks.get_mut(&price) {
-                let mut i = 0;
-                while i < resting_orders.len() && qty_left > 0 {
-                    let resting_order = &mut resting_orders[i];
-                    let trade_qty = qty_left.min(resting_order.qty);
-                    
-                    trades.push(Order {
-                        id: resting_order.id,
-                        price,
-                        qty: trade_qty,
-                        is_buy: false, 
-                        time: Utc::now().to_string(),
-                        order_type: OrderType::Limit,
-                        // user_id: resting_order.user_id,
-                    });
-                    
-                    resting_order.qty -= trade_qty;
-                    qty_left -= trade_qty;
-                    orderbook.last_trade_price = Some(price);
-                    orderbook.current_price = Some(price);
-                    
-                    if resting_order.qty == 0 {
-                        resting_orders.remove(i);
-                    } else {
-                        i += 1;
+            };
+
+            if incoming_order.self_trade_prevention != SelfTradePrevention::Off
+                && resting_order.user_id == incoming_order.user_id
+            {
+                let resting_qty = resting_order.total_qty();
+                let (taker_cancel, resting_cancel) = match incoming_order.self_trade_prevention {
+                    SelfTradePrevention::CancelNewest => (qty_left, Quantity::ZERO),
+                    SelfTradePrevention::CancelOldest => (Quantity::ZERO, resting_qty),
+                    SelfTradePrevention::CancelBoth => (qty_left, resting_qty),
+                    SelfTradePrevention::DecrementAndCancel | SelfTradePrevention::Off => {
+                        let decrement = qty_left.min(resting_qty);
+                        (decrement, decrement)
                     }
+                };
+
+                if !taker_cancel.is_zero() {
+                    qty_left -= taker_cancel;
+                    orderbook.self_trades_prevented.push(SelfTradePrevented {
+                        order_id: incoming_order.id,
+                        user_id: incoming_order.user_id,
+                        cancelled_qty: taker_cancel,
+                    });
                 }
-                
-                if resting_orders.is_empty() {
-                    orderbook.asks.remove(&price);
+                if !resting_cancel.is_zero() {
+                    orderbook.self_trades_prevented.push(SelfTradePrevented {
+                        order_id: resting_order.id,
+                        user_id: resting_order.user_id,
+                        cancelled_qty: resting_cancel,
+                    });
+                    if resting_cancel < resting_qty {
+                        let new_qty = resting_qty - resting_cancel;
+                        let displayed = resting_order.qty;
+                        resting_order.qty = resting_order.qty.min(new_qty);
+                        resting_order.hidden_qty = new_qty - resting_order.qty;
+                        untrack_order(&mut orderbook.user_orders, resting_order, resting_cancel, false);
+                        if resting_order.qty != displayed {
+                            orderbook.book_events.push(BookEvent::new(BookEventKind::Modify, resting_order, resting_order.qty));
+                        }
+                    } else if let Some(cancelled) = resting_orders.pop_front() {
+                        orderbook.book_events.push(BookEvent::new(BookEventKind::Cancel, &cancelled, cancelled.qty));
+                        untrack_order(&mut orderbook.user_orders, &cancelled, resting_cancel, true);
+                        orderbook.closed_orders.push(cancelled.id);
+                        forget_order(&mut orderbook.order_index, &mut orderbook.expiries, &cancelled);
+                    }
                 }
-                
-                if qty_left == 0 {
+                continue;
+            }
+
+            let mut trade_qty = qty_left.min(resting_order.qty);
+            if let Some(budget) = budget_left.as_mut() {
+                trade_qty = trade_qty.min(orderbook.instrument.spec.affordable_quantity(price, *budget));
+                if trade_qty.is_zero() {
+                    qty_left = Quantity::ZERO;
                     break;
                 }
+                *budget -= orderbook.instrument.spec.quote_amount(price, trade_qty);
             }
-        }
-    } else {
-        let mut prices: Vec<u64> = orderbook.bids.keys().cloned().collect();
-        prices.sort_by(|a, b| b.cmp(a)); // Descending order
-        for price in prices {
-            if order_type == OrderType::Limit && price < incoming_order.price {
-                break;
-            }
-            
-            if let Some(resting_orders) = orderbook.bids.get_mut(&price) {
-                let mut i = 0;
-                while i < resting_orders.len() && qty_left > 0 {
-                    let resting_order = &mut resting_orders[i];
-                    let trade_qty = qty_left.min(resting_order.qty);
-                    trades.push(Order {
-                        id: resting_order.id,
-                        price,
-                        qty: trade_qty,
-                        is_buy: true, 
-                        time: Utc::now().to_string(),
-                        order_type: OrderType::Limit,
-                        // user_id: resting_order.user_id,
-                    });
-                    
-                    resting_order.qty -= trade_qty;
-                    qty_left -= trade_qty;
-                    orderbook.last_trade_price = Some(price);
-                    orderbook.current_price = Some(price);
-                    
-                    if resting_order.qty == 0 {
-                        resting_orders.remove(i);
-                    } else {
-                        i += 1;
+
+            trades.push(Trade {
+                trade_id: orderbook.next_trade_id,
+                symbol: orderbook.instrument.symbol.clone(),
+                price,
+                qty: trade_qty,
+                maker_order_id: resting_order.id,
+                taker_order_id: incoming_order.id,
+                maker_user_id: resting_order.user_id,
+                taker_user_id: incoming_order.user_id,
+                aggressor_side: if incoming_order.is_buy { Side::Buy } else { Side::Sell },
+                time: now,
+                maker_fee: Fee::default(),
+                taker_fee: Fee::default(),
+            });
+            orderbook.book_events.push(BookEvent {
+                trade_id: Some(orderbook.next_trade_id),
+                ..BookEvent::new(BookEventKind::Execute, resting_order, trade_qty)
+            });
+            orderbook.next_trade_id += 1;
+
+            resting_order.qty -= trade_qty;
+            qty_left -= trade_qty;
+            untrack_order(&mut orderbook.user_orders, resting_order, trade_qty, resting_order.total_qty().is_zero());
+            orderbook.last_trade_price = Some(price);
+            orderbook.current_price = Some(price);
+
+            if resting_order.qty.is_zero()
+                && let Some(mut filled) = resting_orders.pop_front()
+            {
+                if !filled.hidden_qty.is_zero() {
+                    // Refill the iceberg's displayed slice at the back of the queue
+                    let peak = filled.display_qty.unwrap_or(filled.hidden_qty);
+                    filled.qty = peak.min(filled.hidden_qty);
+                    filled.hidden_qty -= filled.qty;
+                    orderbook.book_events.push(BookEvent::new(BookEventKind::Add, &filled, filled.qty));
+                    let id = filled.id;
+                    let position = resting_orders.push(filled);
+                    if let Some(location) = orderbook.order_index.get_mut(&id) {
+                        location.position = position;
                     }
-                }
-                
-                if resting_orders.is_empty() {
-                    orderbook.bids.remove(&price);
-                }
-                
-                if qty_left == 0 {
-                    break;
+                } else {
+                    orderbook.closed_orders.push(filled.id);
+                    forget_order(&mut orderbook.order_index, &mut orderbook.expiries, &filled);
                 }
             }
         }
+
+        if resting_orders.is_empty() {
+            book.remove(&price);
+        }
     }
 
-    if qty_left > 0 && order_type == OrderType::Limit {
+    if !qty_left.is_zero() && order_type == OrderType::Limit && rests {
         let mut remaining_order = incoming_order.clone();
         remaining_order.qty = qty_left;
         add_order(orderbook, remaining_order);
@@ -121,57 +272,1170 @@ pub fn match_order(orderbook: &mut OrderBook, incoming_order: &Order, order_type
     trades
 }
 
+/// Drops an order that has left the book from the id index and expiry queue.
+fn forget_order(order_index: &mut HashMap<OrderId, OrderLocation>, expiries: &mut BTreeSet<(i64, OrderId)>, order: &Order) {
+    order_index.remove(&order.id);
+    if let Some(expires_at) = order.expires_at {
+        expiries.remove(&(expires_at, order.id));
+    }
+}
+
+/// Counts an order entering the book against its user.
+fn track_order(user_orders: &mut HashMap<u32, UserOrders>, order: &Order) {
+    let user = user_orders.entry(order.user_id).or_default();
+    user.open += 1;
+    if order.is_buy {
+        user.buy_qty += order.total_qty();
+    }
+}
+
+/// Takes `qty` of an order off its user's totals, and the order itself once
+/// it has `closed`.
+fn untrack_order(user_orders: &mut HashMap<u32, UserOrders>, order: &Order, qty: Quantity, closed: bool) {
+    let Some(user) = user_orders.get_mut(&order.user_id) else {
+        return;
+    };
+    if order.is_buy {
+        user.buy_qty = user.buy_qty.saturating_sub(qty);
+    }
+    if closed {
+        user.open = user.open.saturating_sub(1);
+        if user.open == 0 {
+            user_orders.remove(&order.user_id);
+        }
+    }
+}
+
+/// Lowest ask for a buy, highest bid for a sell.
+fn best_opposite_price(orderbook: &OrderBook, is_buy: bool) -> Option<Price> {
+    if is_buy {
+        orderbook.asks.keys().next().copied()
+    } else {
+        orderbook.bids.keys().next_back().copied()
+    }
+}
+
+/// Quantity resting on the opposite side that the incoming order could trade against.
+fn available_quantity(orderbook: &OrderBook, incoming_order: &Order, order_type: OrderType) -> Quantity {
+    let levels: Box<dyn Iterator<Item = (&Price, &PriceLevel)>> = if incoming_order.is_buy {
+        Box::new(orderbook.asks.iter())
+    } else {
+        Box::new(orderbook.bids.iter().rev())
+    };
+
+    let mut available = Quantity::ZERO;
+    for (price, level) in levels {
+        if order_type == OrderType::Limit && !crosses(incoming_order, *price) {
+            break;
+        }
+        available += level.iter().map(|o| o.total_qty()).sum::<Quantity>();
+        if available >= incoming_order.qty {
+            break;
+        }
+    }
+    available
+}
+
+/// Where a post-only Reprice order that would cross `touch` rests instead: one
+/// tick behind it. A buy against an ask at the minimum tick has nowhere to go.
+fn repriced_price(orderbook: &OrderBook, is_buy: bool, touch: Price) -> Option<Price> {
+    let tick_size = orderbook.instrument.spec.tick_size;
+    if !is_buy {
+        Some(touch + tick_size)
+    } else if touch > tick_size {
+        Some(touch - tick_size)
+    } else {
+        None
+    }
+}
+
+/// Rejects a post-only order that could not be placed as a maker order: a
+/// Reject order that would cross the book, or a Reprice order with no price
+/// left behind the touch.
+fn check_post_only(orderbook: &OrderBook, order: &Order) -> Result<(), OrderError> {
+    let Some(touch) = best_opposite_price(orderbook, order.is_buy).filter(|touch| crosses(order, *touch)) else {
+        return Ok(());
+    };
+    match order.post_only {
+        PostOnly::Off => Ok(()),
+        PostOnly::Reject => Err(OrderError::PostOnlyWouldCross),
+        PostOnly::Reprice => match repriced_price(orderbook, order.is_buy, touch) {
+            Some(_) => Ok(()),
+            None => Err(OrderError::InvalidPrice(format!(
+                "no price below the best ask {} to reprice to",
+                orderbook.instrument.spec.format_price(touch)
+            ))),
+        },
+    }
+}
+
+fn crosses(incoming_order: &Order, resting_price: Price) -> bool {
+    if incoming_order.is_buy {
+        resting_price <= incoming_order.price
+    } else {
+        resting_price >= incoming_order.price
+    }
+}
+
+pub fn cancel_order(orderbook: &mut OrderBook, order_id: OrderId) -> Option<Order> {
+    let location = orderbook.order_index.remove(&order_id)?;
+    let book = orderbook.levels_mut(&location);
+
+    let resting_orders = book.get_mut(&location.price)?;
+    let cancelled = resting_orders.remove(location.position);
+    let moved = resting_orders.compact();
+    if resting_orders.is_empty() {
+        book.remove(&location.price);
+    }
+    for (id, position) in moved {
+        if let Some(location) = orderbook.order_index.get_mut(&id) {
+            location.position = position;
+        }
+    }
+    if let Some(expires_at) = cancelled.as_ref().and_then(|o| o.expires_at) {
+        orderbook.expiries.remove(&(expires_at, order_id));
+    }
+    if let Some(order) = cancelled.as_ref() {
+        untrack_order(&mut orderbook.user_orders, order, order.total_qty(), true);
+    }
+    orderbook.closed_orders.push(order_id);
+    if let Some(order) = cancelled.as_ref()
+        && !location.is_stop
+    {
+        orderbook.book_events.push(BookEvent::new(BookEventKind::Cancel, order, order.qty));
+    }
+
+    update_best_prices(orderbook);
+    cancelled
+}
+
+/// Removes every resting GTD/DAY order whose deadline is at or before `now`.
+pub fn expire_orders(orderbook: &mut OrderBook, now: i64) -> Vec<Order> {
+    let expired: Vec<OrderId> = orderbook
+        .expiries
+        .range(..=(now, OrderId::MAX))
+        .map(|(_, id)| *id)
+        .collect();
+
+    expired
+        .into_iter()
+        .filter_map(|id| cancel_order(orderbook, id))
+        .collect()
+}
+
+/// Amends a resting order. Reducing quantity at the same price keeps the order's
+/// place in the queue; any other change requeues it and runs it through matching
+/// again, so a new price may cross the book. Untriggered stop orders are not
+/// resting and are left alone.
+pub fn modify_order(orderbook: &mut OrderBook, order_id: OrderId, new_price: Option<Price>, new_qty: Option<Quantity>, now: i64) -> Option<Vec<Trade>> {
+    let location = *orderbook.order_index.get(&order_id)?;
+    if location.is_stop {
+        return None;
+    }
+    let book = orderbook.levels_mut(&location);
+    let resting_order = book.get_mut(&location.price)?.get_mut(location.position)?;
+
+    let price = new_price.unwrap_or(resting_order.price);
+    let qty = new_qty.unwrap_or(resting_order.total_qty());
+    if price == resting_order.price && qty <= resting_order.total_qty() {
+        // Shrink the hidden reserve before the displayed slice
+        let displayed = resting_order.qty;
+        let removed = resting_order.total_qty() - qty;
+        resting_order.qty = resting_order.qty.min(qty);
+        resting_order.hidden_qty = qty - resting_order.qty;
+        let event = (resting_order.qty != displayed).then(|| BookEvent::new(BookEventKind::Modify, resting_order, resting_order.qty));
+        let order = resting_order.clone();
+        untrack_order(&mut orderbook.user_orders, &order, removed, false);
+        orderbook.book_events.extend(event);
+        return Some(Vec::new());
+    }
+
+    let mut amended_order = cancel_order(orderbook, order_id)?;
+    amended_order.price = price;
+    amended_order.qty = qty;
+    amended_order.hidden_qty = Quantity::ZERO;
+    amended_order.time = now;
+    Some(match_order(orderbook, &amended_order, OrderType::Limit, now))
+}
+
 fn update_best_prices(orderbook: &mut OrderBook) {
     orderbook.current_best_bid = orderbook.bids.keys().max().copied();
     orderbook.current_best_ask = orderbook.asks.keys().min().copied();
 }
 
-pub fn process_order(order_data: &Value) -> Result<ProcessOrderResult, Box<dyn std::error::Error>> {
+/// When an order placed at `now` (unix seconds) leaves the book unfilled, if ever.
+fn expiry(time_in_force: TimeInForce, expire_time: Option<i64>, now: i64) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    Ok(match time_in_force {
+        TimeInForce::GTD => {
+            let expire_time = expire_time.ok_or("GTD orders require an expire_time")?;
+            if expire_time <= now {
+                return Err("expire_time is in the past".into());
+            }
+            Some(expire_time)
+        }
+        // Next UTC midnight
+        TimeInForce::DAY => Some((now / 86_400 + 1) * 86_400),
+        _ => None,
+    })
+}
+
+/// Headroom over the reference price a Market or Stop buy without a
+/// max_spend reserves for, in basis points.
+const MARKET_BUY_SLIPPAGE_BPS: u128 = 500;
+
+/// What a market-priced buy of `qty` reserves and may spend: its max_spend, or
+/// its quantity at the stop price (else the best ask, else the last trade)
+/// plus slippage.
+fn market_buy_reserve(orderbook: &OrderBook, qty: Quantity, stop_price: Option<Price>, max_spend: Option<&Decimal>) -> Result<Amount, OrderError> {
+    if let Some(max_spend) = max_spend {
+        return match max_spend.to_units(ASSET_DECIMALS) {
+            Some(units) if units > 0 => Ok(Amount(units)),
+            _ => Err(OrderError::InvalidAmount(format!("max_spend must be a positive number with at most {} decimals, got {}", ASSET_DECIMALS, max_spend))),
+        };
+    }
+    let reference = stop_price
+        .or(orderbook.current_best_ask)
+        .or(orderbook.last_trade_price)
+        .ok_or_else(|| OrderError::InvalidPrice("no reference price to reserve funds at; set max_spend".to_string()))?;
+    let cost = orderbook.instrument.spec.quote_amount(reference, qty).0 as u128 * (10_000 + MARKET_BUY_SLIPPAGE_BPS) / 10_000;
+    Ok(Amount(u64::try_from(cost).unwrap_or(u64::MAX)))
+}
+
+/// The clock is read once: that instant decides GTD validity and DAY expiry
+/// and stamps the order and its trades.
+pub fn process_order(order_data: &Value, clock: &dyn Clock) -> Result<ProcessOrderResult, Box<dyn std::error::Error>> {
+    let now = clock.now();
     let order_input: CreateOrderInput = serde_json::from_value(order_data.clone())?;
 
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    let orderbook = orderbooks
+        .get_mut(&order_input.symbol)
+        .ok_or(OrderError::UnknownSymbol)?;
+    if orderbook.instrument.status == InstrumentStatus::Halted {
+        return Err(OrderError::InstrumentHalted.into());
+    }
+
+    // Funds of this book's expired orders are available again before the new one is checked
+    orderbook.expire_orders(clock.now_secs());
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    release_closed_orders(&mut accounts, orderbook);
+
+    let spec = &orderbook.instrument.spec;
+
+    let is_stop = matches!(order_input.order_type, OrderType::Stop | OrderType::StopLimit);
+    if order_input.post_only != PostOnly::Off && order_input.order_type != OrderType::Limit {
+        return Err("post_only is only supported on Limit orders".into());
+    }
+    if order_input.display_quantity.is_some() && order_input.order_type != OrderType::Limit {
+        return Err("display_quantity is only supported on Limit orders".into());
+    }
+
+    // Market and Stop orders execute at whatever the book offers
+    let price = match order_input.order_type {
+        OrderType::Limit | OrderType::StopLimit => spec.parse_price(&order_input.price)?,
+        OrderType::Market | OrderType::Stop => Price::ZERO,
+    };
+    let qty = spec.parse_quantity(&order_input.quantity)?;
+    let display_qty = order_input
+        .display_quantity
+        .as_ref()
+        .map(|q| spec.parse_quantity(q))
+        .transpose()?;
+    let stop_price = match &order_input.stop_price {
+        Some(stop_price) if is_stop => Some(spec.parse_price(stop_price)?),
+        None if is_stop => return Err("Stop orders require a stop_price".into()),
+        _ => None,
+    };
+
+    let expires_at = expiry(order_input.time_in_force, order_input.expire_time, clock.now_secs())?;
+
+    // Sells reserve the base they offer and priced buys their full cost. A
+    // market-priced buy reserves its max_spend, or its quantity at the stop
+    // price (or the touch) plus slippage, and may not spend more than that.
+    let instrument = &orderbook.instrument;
+    let is_buy = order_input.side == Side::Buy;
+    if order_input.max_spend.is_some() && !(is_buy && price.is_zero()) {
+        return Err("max_spend is only supported on Market and Stop buys".into());
+    }
+    let (asset, reserve, budget) = if !is_buy {
+        (&instrument.base_asset, spec.base_amount(qty), None)
+    } else if price.is_zero() {
+        let reserve = market_buy_reserve(orderbook, qty, stop_price, order_input.max_spend.as_ref())?;
+        (&instrument.quote_asset, reserve, Some(reserve))
+    } else {
+        (&instrument.quote_asset, spec.quote_amount(price, qty), None)
+    };
+
+    let mut order = Order {
+        // Assigned once the order has passed its risk checks and funds are reserved
+        id: 0,
+        user_id: order_input.user_id,
+        price,
+        qty,
+        display_qty,
+        hidden_qty: Quantity::ZERO,
+        is_buy,
+        order_type: order_input.order_type,
+        stop_price,
+        post_only: order_input.post_only,
+        self_trade_prevention: order_input.self_trade_prevention,
+        time_in_force: order_input.time_in_force,
+        expires_at,
+        time: now,
+        budget,
+    };
+    // A post-only Reject order that crosses is reported as rejected below
+    if order.post_only == PostOnly::Reprice {
+        check_post_only(orderbook, &order)?;
+    }
+    RISK.read().unwrap().check(&order, orderbook, &accounts, None)?;
+
     let order_id = {
         let mut id = NEXT_ORDER_ID.lock().unwrap();
         let current_id = *id;
+        accounts.lock(current_id, order_input.user_id, asset, reserve)?;
         *id += 1;
         current_id
     };
-    
-    let price_int = (order_input.price * 100.0) as u64;
+    order.id = order_id;
 
-    let order = Order {
-        id: order_id,
-        price: price_int,
-        qty: order_input.quantity as u64,
-        is_buy: order_input.side == Side::Buy,
-        order_type: order_input.order_type,
-        time: Utc::now().to_string(),
+    let mut trades = match order_input.order_type {
+        OrderType::Market => orderbook.match_order(&order, OrderType::Market, now),
+        OrderType::Limit => orderbook.match_order(&order, OrderType::Limit, now),
+        OrderType::Stop | OrderType::StopLimit => {
+            orderbook.add_stop_order(order.clone());
+            orderbook.trigger_stop_orders(now)
+        }
     };
 
+    settle_trades(&mut accounts, orderbook, &mut trades);
+    let self_trades_prevented = std::mem::take(&mut orderbook.self_trades_prevented);
+
+    // Trades may include fills of stop orders this order triggered
+    let filled_quantity: Quantity = trades
+        .iter()
+        .filter(|t| t.taker_order_id == order_id || t.maker_order_id == order_id)
+        .map(|t| t.qty)
+        .sum();
+    let remaining_quantity = order.qty.saturating_sub(filled_quantity);
+
+    let status = if remaining_quantity.is_zero() {
+        OrderStatus::Filled
+    } else if orderbook.order_index.get(&order_id).is_some_and(|l| l.is_stop) {
+        OrderStatus::Pending
+    } else if orderbook.get_order(order_id).is_some() {
+        if filled_quantity.is_zero() { OrderStatus::New } else { OrderStatus::PartiallyFilled }
+    } else if order.post_only != PostOnly::Off && filled_quantity.is_zero() {
+        OrderStatus::Rejected
+    } else if order.time_in_force == TimeInForce::FOK {
+        OrderStatus::Killed
+    } else {
+        OrderStatus::Cancelled
+    };
+
+    Ok(ProcessOrderResult {
+        order_id,
+        status,
+        time_in_force: order.time_in_force,
+        repriced_price: orderbook
+            .get_order(order_id)
+            .map(|o| o.price)
+            .filter(|price| order.post_only == PostOnly::Reprice && *price != order.price),
+        trades,
+        self_trades_prevented,
+        remaining_quantity,
+        orderbook_state: orderbook_state(orderbook),
+    })
+}
+
+pub fn process_cancel(cancel_data: &Value) -> Result<CancelOrderResult, Box<dyn std::error::Error>> {
+    let cancel_input: CancelOrderInput = serde_json::from_value(cancel_data.clone())?;
+
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    for orderbook in orderbooks.values_mut() {
+        if let Some(order) = orderbook.cancel_order(cancel_input.order_id) {
+            release_closed_orders(&mut ACCOUNTS.lock().unwrap(), orderbook);
+            return Ok(CancelOrderResult {
+                order_id: order.id,
+                cancelled_quantity: order.total_qty(),
+                orderbook_state: orderbook_state(orderbook),
+            });
+        }
+    }
+
+    Err("Order not found or already filled".into())
+}
+
+pub fn process_modify(modify_data: &Value, clock: &dyn Clock) -> Result<ModifyOrderResult, Box<dyn std::error::Error>> {
+    let modify_input: ModifyOrderInput = serde_json::from_value(modify_data.clone())?;
+
+    if modify_input.price.is_none() && modify_input.quantity.is_none() {
+        return Err("Nothing to modify: provide a price and/or quantity".into());
+    }
+
     let mut orderbooks = ORDERBOOKS.lock().unwrap();
     let orderbook = orderbooks
-        .get_mut(&order_input.symbol)
-        .ok_or("Invalid Symbol")?;
+        .values_mut()
+        .find(|orderbook| orderbook.order_index.contains_key(&modify_input.order_id))
+        .ok_or("Order not found or already filled")?;
+    if orderbook.instrument.status == InstrumentStatus::Halted {
+        return Err(OrderError::InstrumentHalted.into());
+    }
+
+    let price = modify_input.price.as_ref().map(|p| orderbook.instrument.spec.parse_price(p)).transpose()?;
+    let qty = modify_input.quantity.as_ref().map(|q| orderbook.instrument.spec.parse_quantity(q)).transpose()?;
+
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    let trades = amend_order(orderbook, &mut accounts, &RISK.read().unwrap(), modify_input.order_id, price, qty, clock.now())?;
+    let remaining_quantity = orderbook
+        .get_order(modify_input.order_id)
+        .map(|o| o.total_qty())
+        .unwrap_or_default();
+
+    Ok(ModifyOrderResult {
+        order_id: modify_input.order_id,
+        trades,
+        self_trades_prevented: std::mem::take(&mut orderbook.self_trades_prevented),
+        remaining_quantity,
+        orderbook_state: orderbook_state(orderbook),
+    })
+}
 
-    let trades = match order_input.order_type {
-        OrderType::Market => orderbook.match_order(&order, OrderType::Market),
-        OrderType::Limit => orderbook.match_order(&order, OrderType::Limit),
+/// Applies an amendment and settles what it trades. The amended order is
+/// checked and reserved for before the book is touched, so a modify that
+/// cannot go ahead leaves the order as it was.
+fn amend_order(
+    orderbook: &mut OrderBook,
+    accounts: &mut Accounts,
+    risk: &RiskEngine,
+    order_id: OrderId,
+    price: Option<Price>,
+    qty: Option<Quantity>,
+    now: i64,
+) -> Result<Vec<Trade>, Box<dyn std::error::Error>> {
+    // A pending stop's trigger and funds were set for the order as placed; it is
+    // cancelled and placed again instead
+    if orderbook.order_index.get(&order_id).is_some_and(|location| location.is_stop) {
+        return Err(OrderError::PendingStopNotAmendable.into());
+    }
+    let order = orderbook.get_order(order_id).ok_or("Order not found or already filled")?;
+    let amended = Order {
+        price: price.unwrap_or(order.price),
+        qty: qty.unwrap_or(order.total_qty()),
+        hidden_qty: Quantity::ZERO,
+        ..order.clone()
     };
+    if amended.price != order.price {
+        check_post_only(orderbook, &amended)?;
+    }
+    risk.check(&amended, orderbook, accounts, Some(order))?;
+    if let Some((asset, reserve)) = required_reserve(&orderbook.instrument, &amended) {
+        accounts.relock(amended.id, amended.user_id, asset, reserve)?;
+    }
 
-    let filled_quantity: u64 = trades.iter().map(|t| t.qty).sum();
-    let remaining_quantity = order.qty.saturating_sub(filled_quantity);
+    let mut trades = orderbook.modify_order(order_id, price, qty, now).ok_or("Order not found or already filled")?;
+    settle_trades(accounts, orderbook, &mut trades);
+    Ok(trades)
+}
+
+/// Sweeps orders that have expired by the clock's time out of every book.
+pub fn process_expiries(clock: &dyn Clock) -> Vec<Order> {
+    let now = clock.now_secs();
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    orderbooks
+        .values_mut()
+        .flat_map(|orderbook| {
+            let expired = orderbook.expire_orders(now);
+            release_closed_orders(&mut accounts, orderbook);
+            expired
+        })
+        .collect()
+}
+
+/// Charges each fill's fees and moves its funds between buyer and seller, then
+/// returns whatever is still reserved for orders that have left the book.
+fn settle_trades(accounts: &mut Accounts, orderbook: &mut OrderBook, trades: &mut [Trade]) {
+    for trade in trades {
+        accounts.settle(trade, &orderbook.instrument);
+    }
+    // Self-trade prevention shrinks orders without a fill; what they reserve shrinks with them
+    for prevented in &orderbook.self_trades_prevented {
+        if let Some(order) = orderbook.get_order(prevented.order_id)
+            && let Some((_, reserve)) = required_reserve(&orderbook.instrument, order)
+        {
+            accounts.trim_lock(order.id, reserve);
+        }
+    }
+    release_closed_orders(accounts, orderbook);
+}
+
+/// What an open order has to keep reserved: the base a sell offers, or what a
+/// priced buy costs at its limit. A market-priced buy spends out of its
+/// budget instead and has no fixed requirement.
+fn required_reserve<'a>(instrument: &'a Instrument, order: &Order) -> Option<(&'a str, Amount)> {
+    let spec = &instrument.spec;
+    if !order.is_buy {
+        Some((&instrument.base_asset, spec.base_amount(order.total_qty())))
+    } else if order.budget.is_none() {
+        Some((&instrument.quote_asset, spec.quote_amount(order.price, order.total_qty())))
+    } else {
+        None
+    }
+}
+
+fn release_closed_orders(accounts: &mut Accounts, orderbook: &mut OrderBook) {
+    for order_id in orderbook.drain_closed_orders() {
+        accounts.release(order_id);
+    }
+}
+
+/// Credits a user's available balance in an asset one of the instruments trades.
+pub fn process_deposit(deposit_data: &Value, clock: &dyn Clock) -> Result<TransferResult, Box<dyn std::error::Error>> {
+    let (input, amount) = parse_transfer(deposit_data)?;
+    let reference = format!("deposit:{}", input.request_id);
+    let balance = ACCOUNTS.lock().unwrap().deposit(input.user_id, &input.asset, amount, clock.now(), reference)?;
+    Ok(TransferResult { user_id: input.user_id, asset: input.asset, balance })
+}
+
+/// Debits a user's available balance, rejecting withdrawals that would need
+/// funds locked for open orders.
+pub fn process_withdraw(withdraw_data: &Value, clock: &dyn Clock) -> Result<TransferResult, Box<dyn std::error::Error>> {
+    let (input, amount) = parse_transfer(withdraw_data)?;
+    let reference = format!("withdraw:{}", input.request_id);
+    let balance = ACCOUNTS.lock().unwrap().withdraw(input.user_id, &input.asset, amount, clock.now(), reference)?;
+    Ok(TransferResult { user_id: input.user_id, asset: input.asset, balance })
+}
+
+fn parse_transfer(transfer_data: &Value) -> Result<(TransferInput, Amount), Box<dyn std::error::Error>> {
+    let input: TransferInput = serde_json::from_value(transfer_data.clone())?;
+    let known = ORDERBOOKS
+        .lock()
+        .unwrap()
+        .values()
+        .any(|orderbook| orderbook.instrument.base_asset == input.asset || orderbook.instrument.quote_asset == input.asset);
+    if !known {
+        return Err(OrderError::UnknownAsset(input.asset).into());
+    }
+    let amount = input
+        .amount
+        .to_units(ASSET_DECIMALS)
+        .ok_or_else(|| OrderError::InvalidAmount(format!("{} is not a non-negative number with at most {} decimals", input.amount, ASSET_DECIMALS)))?;
+    if amount == 0 {
+        return Err(OrderError::InvalidAmount("must be greater than zero".to_string()).into());
+    }
+    Ok((input, Amount(amount)))
+}
+
+/// Ledger entries recorded since the last call, for the worker to persist.
+pub fn drain_ledger_entries() -> Vec<LedgerEntry> {
+    ACCOUNTS.lock().unwrap().drain_ledger_entries()
+}
+
+/// A copy of the accounts, for an audit.
+pub fn capture_accounts() -> Accounts {
+    ACCOUNTS.lock().unwrap().clone()
+}
+
+/// A user's balance in every asset they have held.
+pub fn account_balances(user_id: u32) -> BTreeMap<String, Balance> {
+    ACCOUNTS.lock().unwrap().user_balances(user_id)
+}
+
+/// Applies a journaled command the way the worker did when it was received and
+/// returns the trades it produced. Commands the worker rejected are rejected
+/// again, so the error is only informational.
+pub fn replay_command(command: &Value, clock: &dyn Clock) -> Result<Vec<Trade>, Box<dyn std::error::Error>> {
+    match command["type"].as_str() {
+        Some("cancel") => process_cancel(command).map(|_| Vec::new()),
+        Some("modify") => process_modify(command, clock).map(|result| result.trades),
+        Some("add_instrument") => add_instrument(command).map(|_| Vec::new()),
+        Some("set_instrument_status") => set_instrument_status(command).map(|_| Vec::new()),
+        Some("deposit") => process_deposit(command, clock).map(|_| Vec::new()),
+        Some("withdraw") => process_withdraw(command, clock).map(|_| Vec::new()),
+        Some("expire") => {
+            process_expiries(clock);
+            Ok(Vec::new())
+        }
+        _ => process_order(command, clock).map(|result| result.trades),
+    }
+}
+
+/// Copies of every book, ordered by symbol, the next order id and the
+/// accounts, for a snapshot.
+pub fn capture_books() -> (Vec<OrderBook>, OrderId, Accounts) {
+    let orderbooks = ORDERBOOKS.lock().unwrap();
+    let next_order_id = *NEXT_ORDER_ID.lock().unwrap();
+    let accounts = ACCOUNTS.lock().unwrap().clone();
+    let mut books: Vec<OrderBook> = orderbooks.values().map(snapshot_book).collect();
+    books.sort_by(|a, b| a.instrument.symbol.cmp(&b.instrument.symbol));
+    (books, next_order_id, accounts)
+}
+
+/// A copy of the book to persist. Buffered L3 events are not persisted, so the
+/// copy counts them as numbered: the live book numbers them the same way when
+/// they are drained, and a book restored from the copy carries on after them.
+fn snapshot_book(orderbook: &OrderBook) -> OrderBook {
+    let mut book = orderbook.clone();
+    book.drain_book_events();
+    book
+}
+
+/// Replaces the whole registry and the accounts with those loaded from a snapshot.
+pub fn restore_books(books: Vec<OrderBook>, next_order_id: OrderId, accounts: Accounts) {
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    *orderbooks = books
+        .into_iter()
+        .map(|orderbook| (orderbook.instrument.symbol.clone(), orderbook))
+        .collect();
+    *NEXT_ORDER_ID.lock().unwrap() = next_order_id;
+    *ACCOUNTS.lock().unwrap() = accounts;
+}
+
+/// Every instrument in the registry, ordered by symbol.
+pub fn list_instruments() -> Vec<Instrument> {
+    let orderbooks = ORDERBOOKS.lock().unwrap();
+    let mut instruments: Vec<Instrument> = orderbooks
+        .values()
+        .map(|orderbook| orderbook.instrument.clone())
+        .collect();
+    instruments.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+    instruments
+}
+
+/// Registers a pre-trade check to run on every new and amended order after
+/// the built-in ones.
+pub fn add_risk_check(check: Box<dyn RiskCheck>) {
+    RISK.write().unwrap().add_check(check);
+}
+
+/// Registers a new instrument and opens an empty book for it.
+pub fn add_instrument(instrument_data: &Value) -> Result<Instrument, Box<dyn std::error::Error>> {
+    let config: InstrumentConfig = serde_json::from_value(instrument_data.clone())?;
+    let instrument = Instrument::from_config(&config)?;
+
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    if orderbooks.contains_key(&instrument.symbol) {
+        return Err(format!("Instrument {} already exists", instrument.symbol).into());
+    }
+    orderbooks.insert(instrument.symbol.clone(), OrderBook::new(instrument.clone()));
+    Ok(instrument)
+}
+
+/// Halts or resumes trading in an instrument. Resting orders are left in place.
+pub fn set_instrument_status(status_data: &Value) -> Result<Instrument, Box<dyn std::error::Error>> {
+    let status_input: InstrumentStatusInput = serde_json::from_value(status_data.clone())?;
+
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    let orderbook = orderbooks
+        .get_mut(&status_input.symbol)
+        .ok_or(OrderError::UnknownSymbol)?;
+    orderbook.instrument.status = status_input.status;
+    Ok(orderbook.instrument.clone())
+}
+
+/// Full depth of every book not in `published`, for the first snapshot the
+/// worker's depth feed sends of it.
+pub fn new_book_depths(published: &HashSet<Symbol>) -> Vec<(Instrument, BookDepth)> {
+    let orderbooks = ORDERBOOKS.lock().unwrap();
+    orderbooks
+        .values()
+        .filter(|orderbook| !published.contains(&orderbook.instrument.symbol))
+        .map(|orderbook| (orderbook.instrument.clone(), orderbook.book_depth()))
+        .collect()
+}
+
+/// The displayed quantity now resting at every price level a batch of L3
+/// events touched, zero for levels that have emptied, and the book's last
+/// trade price. Only those levels are aggregated, not the whole book.
+pub fn touched_levels(batch: &BookEventBatch) -> Option<(Vec<LevelChange>, Option<Price>)> {
+    let orderbooks = ORDERBOOKS.lock().unwrap();
+    let orderbook = orderbooks.get(&batch.symbol)?;
+    Some((level_changes(orderbook, &batch.events), orderbook.last_trade_price))
+}
+
+/// Each price level `events` touched, once, with what now rests there.
+fn level_changes(orderbook: &OrderBook, events: &[BookEvent]) -> Vec<LevelChange> {
+    let touched: BTreeSet<(bool, Price)> = events.iter().map(|event| (event.side == Side::Buy, event.price)).collect();
+    touched
+        .into_iter()
+        .map(|(is_buy, price)| {
+            let side = if is_buy { Side::Buy } else { Side::Sell };
+            LevelChange { side, price, quantity: orderbook.level_quantity(side, price) }
+        })
+        .collect()
+}
+
+/// Resting orders of one book for an L3 snapshot. The worker publishes the
+/// buffered events before taking requests, so the orders are as of the last
+/// event sent.
+pub fn book_orders(symbol: &Symbol) -> Result<BookOrders, OrderError> {
+    let orderbooks = ORDERBOOKS.lock().unwrap();
+    let orderbook = orderbooks.get(symbol).ok_or(OrderError::UnknownSymbol)?;
+    Ok(resting_orders(orderbook))
+}
+
+/// Bids best first, then asks best first, each level in time priority.
+fn resting_orders(orderbook: &OrderBook) -> BookOrders {
+    BookOrders {
+        symbol: orderbook.instrument.symbol.clone(),
+        spec: orderbook.instrument.spec.clone(),
+        seq: orderbook.next_event_seq - 1,
+        bids: orderbook.bids.values().rev().flat_map(|level| level.iter()).cloned().collect(),
+        asks: orderbook.asks.values().flat_map(|level| level.iter()).cloned().collect(),
+    }
+}
 
-    let orderbook_state = OrderBookState {
-        symbol: orderbook.symbol.clone(),
+/// L3 events buffered in every book since the last call.
+pub fn drain_book_events() -> Vec<BookEventBatch> {
+    let mut orderbooks = ORDERBOOKS.lock().unwrap();
+    orderbooks
+        .values_mut()
+        .map(|orderbook| BookEventBatch {
+            symbol: orderbook.instrument.symbol.clone(),
+            events: orderbook.drain_book_events(),
+        })
+        .filter(|batch| !batch.events.is_empty())
+        .collect()
+}
+
+fn orderbook_state(orderbook: &OrderBook) -> OrderBookState {
+    OrderBookState {
+        symbol: orderbook.instrument.symbol.clone(),
+        spec: orderbook.instrument.spec.clone(),
         current_price: orderbook.current_price,
         best_bid: orderbook.current_best_bid,
         best_ask: orderbook.current_best_ask,
         last_trade_price: orderbook.last_trade_price,
-    };
+    }
+}
 
-    Ok(ProcessOrderResult {
-        order_id,
-        trades,
-        remaining_quantity,
-        orderbook_state,
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::{NOW, book, fills, limit};
+
+    #[test]
+    fn cancel_finds_orders_through_the_index() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+        orderbook.add_order(limit(2, false, 100, 5));
+        orderbook.add_order(limit(3, false, 101, 5));
+
+        assert_eq!(orderbook.get_order(2).map(|o| o.qty), Some(Quantity(5)));
+        assert_eq!(orderbook.cancel_order(2).map(|o| o.id), Some(2));
+        assert!(orderbook.cancel_order(2).is_none());
+        assert!(orderbook.get_order(2).is_none());
+
+        let trades = orderbook.match_order(&limit(4, true, 101, 7), OrderType::Limit, NOW);
+        assert_eq!(fills(&trades), vec![(1, 5), (3, 2)]);
+        assert_eq!(orderbook.get_order(3).map(|o| o.qty), Some(Quantity(3)));
+        assert!(orderbook.validate().is_empty());
+    }
+
+    #[test]
+    fn cancelling_most_of_a_level_compacts_it() {
+        let mut orderbook = book();
+        for id in 1..=10 {
+            orderbook.add_order(limit(id, false, 100, 1));
+        }
+        for id in [2, 3, 4, 5, 6, 8] {
+            orderbook.cancel_order(id);
+        }
+
+        let level = &orderbook.asks[&Price(100)];
+        assert_eq!(level.tombstones, 0);
+        assert_eq!(level.orders.len(), 4);
+        assert!(orderbook.validate().is_empty());
+
+        // Positions were renumbered, so lookups and time priority still hold
+        assert_eq!(orderbook.get_order(9).map(|o| o.id), Some(9));
+        assert_eq!(orderbook.cancel_order(9).map(|o| o.id), Some(9));
+        let trades = orderbook.match_order(&limit(11, true, 100, 3), OrderType::Limit, NOW);
+        assert_eq!(fills(&trades), vec![(1, 1), (7, 1), (10, 1)]);
+        assert!(orderbook.asks.is_empty());
+        assert!(orderbook.validate().is_empty());
+    }
+
+    #[test]
+    fn tombstones_below_half_are_left_in_place() {
+        let mut orderbook = book();
+        for id in 1..=5 {
+            orderbook.add_order(limit(id, true, 100, 1));
+        }
+        orderbook.cancel_order(3);
+
+        let level = &orderbook.bids[&Price(100)];
+        assert_eq!(level.tombstones, 1);
+        assert_eq!(level.orders.len(), 5);
+        assert!(orderbook.validate().is_empty());
+    }
+
+    #[test]
+    fn reducing_quantity_keeps_queue_priority() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+        orderbook.add_order(limit(2, false, 100, 5));
+
+        assert_eq!(orderbook.modify_order(1, None, Some(Quantity(3)), NOW).map(|t| t.len()), Some(0));
+        let trades = orderbook.match_order(&limit(3, true, 100, 4), OrderType::Limit, NOW);
+        assert_eq!(fills(&trades), vec![(1, 3), (2, 1)]);
+    }
+
+    #[test]
+    fn increasing_quantity_or_changing_price_loses_priority() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+        orderbook.add_order(limit(2, false, 100, 5));
+        orderbook.add_order(limit(3, false, 101, 5));
+
+        orderbook.modify_order(1, None, Some(Quantity(6)), NOW);
+        orderbook.modify_order(3, Some(Price(100)), None, NOW);
+        let trades = orderbook.match_order(&limit(4, true, 100, 16), OrderType::Limit, NOW);
+        assert_eq!(fills(&trades), vec![(2, 5), (1, 6), (3, 5)]);
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn modify_to_a_crossing_price_trades() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+        orderbook.add_order(limit(2, true, 98, 3));
+
+        let trades = orderbook.modify_order(2, Some(Price(100)), None, NOW).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 3)]);
+        assert!(orderbook.get_order(2).is_none());
+        assert_eq!(orderbook.get_order(1).map(|o| o.qty), Some(Quantity(2)));
+        assert!(orderbook.modify_order(42, None, Some(Quantity(1)), NOW).is_none());
+    }
+
+    #[test]
+    fn fok_fills_completely_or_not_at_all() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+        orderbook.add_order(limit(2, false, 101, 5));
+
+        let fok = Order { time_in_force: TimeInForce::FOK, ..limit(3, true, 101, 11) };
+        assert!(orderbook.match_order(&fok, OrderType::Limit, NOW).is_empty());
+        assert_eq!(orderbook.get_order(1).map(|o| o.qty), Some(Quantity(5)));
+        assert!(orderbook.get_order(3).is_none());
+
+        let fok = Order { time_in_force: TimeInForce::FOK, ..limit(4, true, 101, 10) };
+        assert_eq!(fills(&orderbook.match_order(&fok, OrderType::Limit, NOW)), vec![(1, 5), (2, 5)]);
+    }
+
+    #[test]
+    fn ioc_remainder_does_not_rest() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+
+        let ioc = Order { time_in_force: TimeInForce::IOC, ..limit(2, true, 100, 8) };
+        assert_eq!(fills(&orderbook.match_order(&ioc, OrderType::Limit, NOW)), vec![(1, 5)]);
+        assert!(orderbook.get_order(2).is_none());
+        assert!(orderbook.bids.is_empty());
+    }
+
+    #[test]
+    fn gtd_orders_expire_at_their_deadline() {
+        let mut orderbook = book();
+        let gtd = Order { time_in_force: TimeInForce::GTD, expires_at: Some(10), ..limit(1, true, 100, 5) };
+        orderbook.add_order(gtd);
+
+        assert!(orderbook.expire_orders(9).is_empty());
+        assert_eq!(orderbook.expire_orders(10).iter().map(|o| o.id).collect::<Vec<_>>(), vec![1]);
+        assert!(orderbook.bids.is_empty() && orderbook.expiries.is_empty());
+    }
+
+    #[test]
+    fn day_orders_expire_at_the_next_utc_midnight() {
+        // 2023-11-14T22:13:20Z
+        let now = 1_700_000_000;
+        let midnight = 1_700_006_400;
+        assert_eq!(expiry(TimeInForce::DAY, None, now).unwrap(), Some(midnight));
+        assert_eq!(expiry(TimeInForce::DAY, None, midnight).unwrap(), Some(midnight + 86_400));
+        assert_eq!(expiry(TimeInForce::GTD, Some(now + 1), now).unwrap(), Some(now + 1));
+        assert!(expiry(TimeInForce::GTD, Some(now), now).is_err());
+        assert!(expiry(TimeInForce::GTD, None, now).is_err());
+        assert_eq!(expiry(TimeInForce::IOC, Some(now + 1), now).unwrap(), None);
+    }
+
+    #[test]
+    fn iceberg_shows_one_peak_and_refills_at_the_back() {
+        let mut orderbook = book();
+        orderbook.add_order(Order { display_qty: Some(Quantity(2)), ..limit(1, false, 100, 5) });
+        orderbook.add_order(limit(2, false, 100, 3));
+        assert_eq!(orderbook.get_depth(1).asks, vec![(Price(100), Quantity(5))]);
+
+        // The first peak trades ahead of order 2; the refill queues behind it
+        let trades = orderbook.match_order(&limit(3, true, 100, 4), OrderType::Limit, NOW);
+        assert_eq!(fills(&trades), vec![(1, 2), (2, 2)]);
+        let iceberg = orderbook.get_order(1).cloned().unwrap();
+        assert_eq!((iceberg.qty, iceberg.hidden_qty), (Quantity(2), Quantity(1)));
+
+        let trades = orderbook.match_order(&limit(4, true, 100, 4), OrderType::Limit, NOW);
+        assert_eq!(fills(&trades), vec![(2, 1), (1, 2), (1, 1)]);
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn fok_counts_hidden_iceberg_quantity() {
+        let mut orderbook = book();
+        orderbook.add_order(Order { display_qty: Some(Quantity(1)), ..limit(1, false, 100, 5) });
+
+        let fok = Order { time_in_force: TimeInForce::FOK, ..limit(2, true, 100, 5) };
+        assert_eq!(fills(&orderbook.match_order(&fok, OrderType::Limit, NOW)).len(), 5);
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn matching_depends_only_on_the_injected_time() {
+        // The same commands applied at their journaled times, once live and
+        // once on replay, make the same expiry decisions and stamp the same trades
+        let commands = [
+            (NOW, Order { time_in_force: TimeInForce::GTD, expires_at: Some(NOW / NANOS_PER_SEC + 60), ..limit(1, false, 100, 5) }),
+            (NOW + 30 * NANOS_PER_SEC, limit(2, false, 101, 5)),
+            (NOW + 90 * NANOS_PER_SEC, limit(3, true, 101, 4)),
+        ];
+        let run = || {
+            let mut orderbook = book();
+            let trades: Vec<Trade> = commands
+                .iter()
+                .flat_map(|(time, order)| orderbook.match_order(&Order { time: *time, ..order.clone() }, OrderType::Limit, *time))
+                .collect();
+            trades.iter().map(|t| (t.maker_order_id, t.qty.0, t.time)).collect::<Vec<_>>()
+        };
+
+        let live = run();
+        assert_eq!(live, vec![(2, 4, NOW + 90 * NANOS_PER_SEC)]);
+        assert_eq!(run(), live);
+    }
+
+    #[test]
+    fn post_only_orders_never_take_liquidity() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+
+        let reject = Order { post_only: PostOnly::Reject, ..limit(2, true, 100, 5) };
+        assert!(orderbook.match_order(&reject, OrderType::Limit, NOW).is_empty());
+        assert!(orderbook.get_order(2).is_none());
+
+        let reprice = Order { post_only: PostOnly::Reprice, ..limit(3, true, 101, 5) };
+        assert!(orderbook.match_order(&reprice, OrderType::Limit, NOW).is_empty());
+        assert_eq!(orderbook.get_order(3).map(|o| o.price), Some(Price(99)));
+        assert_eq!(orderbook.get_order(1).map(|o| o.qty), Some(Quantity(5)));
+    }
+
+    #[test]
+    fn reprice_against_an_ask_at_the_minimum_tick_does_not_rest_at_zero() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 1, 5));
+
+        let reprice = Order { post_only: PostOnly::Reprice, ..limit(2, true, 1, 5) };
+        assert_eq!(check_post_only(&orderbook, &reprice).map_err(|e| e.code()), Err("INVALID_PRICE"));
+        assert!(orderbook.match_order(&reprice, OrderType::Limit, NOW).is_empty());
+        assert!(orderbook.get_order(2).is_none());
+        assert!(orderbook.bids.is_empty());
+    }
+
+    #[test]
+    fn a_post_only_reject_order_may_not_be_amended_across_the_spread() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 100, 5));
+        let bid = Order { post_only: PostOnly::Reject, ..limit(2, true, 99, 5) };
+        orderbook.add_order(bid.clone());
+
+        assert_eq!(check_post_only(&orderbook, &bid), Ok(()));
+        let amended = Order { price: Price(100), ..bid };
+        assert_eq!(check_post_only(&orderbook, &amended).map_err(|e| e.code()), Err("POST_ONLY_WOULD_CROSS"));
+    }
+
+    #[test]
+    fn self_trade_prevention_modes() {
+        let stp = |mode| {
+            let mut orderbook = book();
+            orderbook.add_order(limit(1, false, 100, 3));
+            orderbook.add_order(Order { user_id: 2, ..limit(2, false, 100, 3) });
+            let incoming = Order { self_trade_prevention: mode, ..limit(3, true, 100, 5) };
+            let trades = orderbook.match_order(&incoming, OrderType::Limit, NOW);
+            let prevented: Vec<(u64, u64)> = orderbook.self_trades_prevented.iter().map(|p| (p.order_id, p.cancelled_qty.0)).collect();
+            let resting: Vec<(u64, u64)> = [1, 3].iter().filter_map(|id| orderbook.get_order(*id)).map(|o| (o.id, o.qty.0)).collect();
+            assert!(orderbook.validate().is_empty());
+            (fills(&trades), prevented, resting)
+        };
+
+        assert_eq!(stp(SelfTradePrevention::Off), (vec![(1, 3), (2, 2)], vec![], vec![]));
+        assert_eq!(stp(SelfTradePrevention::CancelNewest), (vec![], vec![(3, 5)], vec![(1, 3)]));
+        assert_eq!(stp(SelfTradePrevention::CancelOldest), (vec![(2, 3)], vec![(1, 3)], vec![(3, 2)]));
+        assert_eq!(stp(SelfTradePrevention::CancelBoth), (vec![], vec![(3, 5), (1, 3)], vec![]));
+        assert_eq!(stp(SelfTradePrevention::DecrementAndCancel), (vec![(2, 2)], vec![(3, 3), (1, 3)], vec![]));
+    }
+
+    #[test]
+    fn stp_decrement_releases_the_resting_orders_funds() {
+        let mut orderbook = book();
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, "BTC", Amount(10 * 100_000_000), NOW, "deposit:1".to_string()).unwrap();
+        accounts.deposit(1, "USD", Amount(1_000 * 100_000_000), NOW, "deposit:2".to_string()).unwrap();
+        let resting = limit(1, false, 10_000, 5_000);
+        accounts.lock(1, 1, "BTC", Amount(5 * 100_000_000)).unwrap();
+        orderbook.add_order(resting);
+
+        let incoming = Order { self_trade_prevention: SelfTradePrevention::DecrementAndCancel, ..limit(2, true, 10_000, 2_000) };
+        let mut trades = orderbook.match_order(&incoming, OrderType::Limit, NOW);
+        settle_trades(&mut accounts, &mut orderbook, &mut trades);
+
+        assert_eq!(orderbook.get_order(1).map(|o| o.total_qty()), Some(Quantity(3_000)));
+        assert_eq!(accounts.balance(1, "BTC").locked, Amount(3 * 100_000_000));
+        assert_eq!(accounts.balance(1, "USD").locked, Amount::ZERO);
+        assert!(accounts.validate().is_empty());
+    }
+
+    #[test]
+    fn touched_levels_aggregate_only_what_the_events_touched() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 10_100, 2_000));
+        orderbook.add_order(limit(2, false, 10_200, 1_000));
+        orderbook.drain_book_events();
+
+        orderbook.match_order(&Order { user_id: 2, ..limit(3, true, 10_100, 3_000) }, OrderType::Limit, NOW);
+        let events = orderbook.drain_book_events();
+        assert_eq!(
+            level_changes(&orderbook, &events),
+            vec![
+                LevelChange { side: Side::Sell, price: Price(10_100), quantity: Quantity::ZERO },
+                LevelChange { side: Side::Buy, price: Price(10_100), quantity: Quantity(1_000) },
+            ]
+        );
+        assert_eq!(orderbook.last_trade_price, Some(Price(10_100)));
+    }
+
+    #[test]
+    fn book_orders_are_in_priority_order_as_of_the_last_drained_event() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 101, 1));
+        orderbook.add_order(limit(2, true, 99, 1));
+        orderbook.add_order(limit(3, true, 100, 1));
+        orderbook.add_order(limit(4, true, 99, 2));
+        orderbook.drain_book_events();
+
+        let orders = resting_orders(&orderbook);
+        assert_eq!(orders.seq, 4);
+        assert_eq!(orders.bids.iter().map(|o| o.id).collect::<Vec<_>>(), vec![3, 2, 4]);
+        assert_eq!(orders.asks.iter().map(|o| o.id).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn a_restored_snapshot_numbers_events_after_those_pending_when_it_was_taken() {
+        let mut orderbook = book();
+        orderbook.add_order(limit(1, false, 101, 1));
+        orderbook.add_order(limit(2, true, 99, 1));
+        let snapshot = serde_json::to_string(&snapshot_book(&orderbook)).unwrap();
+
+        assert_eq!(orderbook.drain_book_events().iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2]);
+        let mut restored: OrderBook = serde_json::from_str(&snapshot).unwrap();
+        restored.cancel_order(1);
+        assert_eq!(restored.drain_book_events().iter().map(|e| e.seq).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn deposits_cannot_overflow_the_asset_supply() {
+        let mut accounts = Accounts::default();
+        let unit = 100_000_000;
+        accounts.deposit(1, "USD", Amount(100_000_000_000 * unit), NOW, "deposit:1".to_string()).unwrap();
+
+        // Over u64::MAX units once added to what another user already holds
+        let overflow = Amount(90_000_000_000 * unit);
+        assert_eq!(accounts.check_deposit("USD", overflow).map_err(|e| e.code()), Err("INVALID_AMOUNT"));
+        assert!(accounts.deposit(2, "USD", overflow, NOW, "deposit:2".to_string()).is_err());
+        assert_eq!(accounts.balance(2, "USD"), Balance::default());
+
+        accounts.deposit(2, "USD", Amount(80_000_000_000 * unit), NOW, "deposit:3".to_string()).unwrap();
+        assert_eq!(accounts.supply("USD"), 180_000_000_000 * unit as u128);
+        assert!(accounts.validate().is_empty());
+    }
+
+    #[test]
+    fn stop_buy_reserves_its_quantity_at_the_stop_price_plus_slippage() {
+        // 2 at 150, plus 5%
+        let reserve = market_buy_reserve(&book(), Quantity(2_000), Some(Price(15_000)), None);
+        assert_eq!(reserve, Ok(Amount(315 * 100_000_000)));
+    }
+
+    #[test]
+    fn market_buy_needs_a_reference_price_or_a_max_spend() {
+        let mut orderbook = book();
+        let reserve = |orderbook: &OrderBook, max_spend: Option<&str>| {
+            market_buy_reserve(orderbook, Quantity(5_000), None, max_spend.map(Decimal::from).as_ref()).map_err(|e| e.code())
+        };
+        assert_eq!(reserve(&orderbook, None), Err("INVALID_PRICE"));
+        assert_eq!(reserve(&orderbook, Some("250")), Ok(Amount(250 * 100_000_000)));
+        assert_eq!(reserve(&orderbook, Some("0")), Err("INVALID_AMOUNT"));
+
+        orderbook.add_order(limit(1, false, 10_000, 5_000));
+        assert_eq!(reserve(&orderbook, None), Ok(Amount(525 * 100_000_000)));
+    }
+
+    struct MaxTwo;
+
+    impl RiskCheck for MaxTwo {
+        fn check(&self, context: &crate::engine::risk::RiskContext) -> Result<(), OrderError> {
+            if context.order.qty > Quantity(2_000) {
+                return Err(OrderError::RiskMaxQuantity { limit: "2".to_string() });
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn modify_runs_registered_risk_checks_on_the_amended_order() {
+        let mut risk = RiskEngine::from_config(&Default::default()).unwrap();
+        risk.add_check(Box::new(MaxTwo));
+        let mut orderbook = book();
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, "USD", Amount(1_000 * 100_000_000), NOW, "deposit:1".to_string()).unwrap();
+        accounts.lock(1, 1, "USD", Amount(200 * 100_000_000)).unwrap();
+        orderbook.add_order(limit(1, true, 10_000, 2_000));
+
+        let result = amend_order(&mut orderbook, &mut accounts, &risk, 1, None, Some(Quantity(3_000)), NOW);
+        assert_eq!(result.unwrap_err().downcast_ref::<OrderError>().map(|e| e.code()), Some("RISK_MAX_QUANTITY"));
+        assert_eq!(orderbook.get_order(1).map(|o| o.total_qty()), Some(Quantity(2_000)));
+        assert_eq!(accounts.balance(1, "USD").locked, Amount(200 * 100_000_000));
+
+        amend_order(&mut orderbook, &mut accounts, &risk, 1, None, Some(Quantity(1_000)), NOW).unwrap();
+        assert_eq!(accounts.balance(1, "USD").locked, Amount(100 * 100_000_000));
+    }
+
+    #[test]
+    fn pending_stops_cannot_be_amended() {
+        let risk = RiskEngine::from_config(&Default::default()).unwrap();
+        let mut orderbook = book();
+        let mut accounts = Accounts::default();
+        let stop = Order { order_type: OrderType::Stop, stop_price: Some(Price(15_000)), budget: Some(Amount(315 * 100_000_000)), ..limit(1, true, 0, 2_000) };
+        orderbook.add_stop_order(stop);
+
+        let result = amend_order(&mut orderbook, &mut accounts, &risk, 1, None, Some(Quantity(4_000)), NOW);
+        assert_eq!(result.unwrap_err().downcast_ref::<OrderError>(), Some(&OrderError::PendingStopNotAmendable));
+        assert!(orderbook.modify_order(1, Some(Price(14_000)), None, NOW).is_none());
+        let order = orderbook.get_order(1).cloned().unwrap();
+        assert_eq!((order.qty, order.stop_price), (Quantity(2_000), Some(Price(15_000))));
+    }
+
+    #[test]
+    fn the_user_index_follows_fills_and_cancels() {
+        let mut orderbook = book();
+        orderbook.add_order(Order { display_qty: Some(Quantity(1_000)), ..limit(1, true, 100, 3_000) });
+        orderbook.add_order(limit(2, true, 99, 2_000));
+        orderbook.add_order(Order { user_id: 2, ..limit(3, false, 101, 1_000) });
+        assert_eq!(orderbook.user_orders[&1], UserOrders { open: 2, buy_qty: Quantity(5_000) });
+
+        // Takes the iceberg's first slice, leaving the refilled order open
+        orderbook.match_order(&Order { user_id: 2, ..limit(4, false, 100, 1_500) }, OrderType::Limit, 0);
+        assert_eq!(orderbook.user_orders[&1], UserOrders { open: 2, buy_qty: Quantity(3_500) });
+        orderbook.modify_order(2, None, Some(Quantity(1_000)), 0);
+        orderbook.cancel_order(1);
+        assert_eq!(orderbook.user_orders[&1], UserOrders { open: 1, buy_qty: Quantity(1_000) });
+        orderbook.cancel_order(2);
+        assert!(!orderbook.user_orders.contains_key(&1));
+        assert!(orderbook.validate().is_empty(), "{:?}", orderbook.validate());
+    }
 }
@@ -0,0 +1,76 @@
+//! Builders shared by the engine's unit tests.
+
+use crate::inputs::{Decimal, Fee, FeeScheduleConfig, Instrument, InstrumentConfig, InstrumentStatus, Order, OrderBook, OrderType, PostOnly, Price, Quantity, SelfTradePrevention, Side, Symbol, TimeInForce, Trade};
+
+/// 2023-11-14T22:13:20Z in unix nanoseconds.
+pub const NOW: i64 = 1_700_000_000_000_000_000;
+
+/// BTCUSD with a 0.01 tick, a 0.001 lot and no fees.
+pub fn instrument() -> Instrument {
+    fee_instrument(FeeScheduleConfig::default())
+}
+
+/// BTCUSD as `instrument()`, charging `fees`.
+pub fn fee_instrument(fees: FeeScheduleConfig) -> Instrument {
+    Instrument::from_config(&InstrumentConfig {
+        symbol: Symbol::from("BTCUSD"),
+        base_asset: "BTC".to_string(),
+        quote_asset: "USD".to_string(),
+        tick_size: Decimal::from("0.01"),
+        lot_size: Decimal::from("0.001"),
+        min_quantity: None,
+        max_quantity: Decimal::from("1000"),
+        status: InstrumentStatus::Trading,
+        fees,
+    })
+    .unwrap()
+}
+
+pub fn book() -> OrderBook {
+    OrderBook::new(instrument())
+}
+
+/// A GTC limit order from user 1, in price and quantity units.
+pub fn limit(id: u64, is_buy: bool, price: u64, qty: u64) -> Order {
+    Order {
+        id,
+        user_id: 1,
+        price: Price(price),
+        qty: Quantity(qty),
+        display_qty: None,
+        hidden_qty: Quantity::ZERO,
+        is_buy,
+        order_type: OrderType::Limit,
+        stop_price: None,
+        post_only: PostOnly::Off,
+        self_trade_prevention: SelfTradePrevention::Off,
+        time_in_force: TimeInForce::GTC,
+        expires_at: None,
+        budget: None,
+        time: NOW,
+    }
+}
+
+/// `(maker_order_id, qty)` of each trade, in order.
+pub fn fills(trades: &[Trade]) -> Vec<(u64, u64)> {
+    trades.iter().map(|trade| (trade.maker_order_id, trade.qty.0)).collect()
+}
+
+/// A BTCUSD fill in which `taker_user_id` bought from `maker_user_id`'s resting
+/// sell, in price and quantity units.
+pub fn fill(maker_user_id: u32, taker_user_id: u32, price: u64, qty: u64, time: i64) -> Trade {
+    Trade {
+        trade_id: 1,
+        symbol: Symbol::from("BTCUSD"),
+        price: Price(price),
+        qty: Quantity(qty),
+        maker_order_id: 1,
+        taker_order_id: 2,
+        maker_user_id,
+        taker_user_id,
+        aggressor_side: Side::Buy,
+        time,
+        maker_fee: Fee::default(),
+        taker_fee: Fee::default(),
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+
+/// Order rejections that clients can act on, each with a stable code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderError {
+    UnknownSymbol,
+    InstrumentHalted,
+    InvalidPrice(String),
+    PriceOffTick { tick_size: String },
+    InvalidQuantity(String),
+    QuantityOffLot { lot_size: String },
+    QuantityBelowMin { min_quantity: String },
+    QuantityAboveMax { max_quantity: String },
+    PostOnlyWouldCross,
+    PendingStopNotAmendable,
+    InsufficientFunds { asset: String, required: String, available: String },
+    UnknownAsset(String),
+    InvalidAmount(String),
+    RiskMaxQuantity { limit: String },
+    RiskMaxNotional { notional: String, limit: String },
+    RiskPriceCollar { reference: String, collar_bps: u32 },
+    RiskMaxOpenOrders { limit: usize },
+    RiskMaxPosition { position: String, limit: String },
+}
+
+impl OrderError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OrderError::UnknownSymbol => "UNKNOWN_SYMBOL",
+            OrderError::InstrumentHalted => "INSTRUMENT_HALTED",
+            OrderError::InvalidPrice(_) => "INVALID_PRICE",
+            OrderError::PriceOffTick { .. } => "PRICE_OFF_TICK",
+            OrderError::InvalidQuantity(_) => "INVALID_QUANTITY",
+            OrderError::QuantityOffLot { .. } => "QUANTITY_OFF_LOT",
+            OrderError::QuantityBelowMin { .. } => "QUANTITY_BELOW_MIN",
+            OrderError::QuantityAboveMax { .. } => "QUANTITY_ABOVE_MAX",
+            OrderError::PostOnlyWouldCross => "POST_ONLY_WOULD_CROSS",
+            OrderError::PendingStopNotAmendable => "PENDING_STOP_NOT_AMENDABLE",
+            OrderError::InsufficientFunds { .. } => "INSUFFICIENT_FUNDS",
+            OrderError::UnknownAsset(_) => "UNKNOWN_ASSET",
+            OrderError::InvalidAmount(_) => "INVALID_AMOUNT",
+            OrderError::RiskMaxQuantity { .. } => "RISK_MAX_QUANTITY",
+            OrderError::RiskMaxNotional { .. } => "RISK_MAX_NOTIONAL",
+            OrderError::RiskPriceCollar { .. } => "RISK_PRICE_COLLAR",
+            OrderError::RiskMaxOpenOrders { .. } => "RISK_MAX_OPEN_ORDERS",
+            OrderError::RiskMaxPosition { .. } => "RISK_MAX_POSITION",
+        }
+    }
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::UnknownSymbol => write!(f, "Invalid Symbol"),
+            OrderError::InstrumentHalted => write!(f, "Trading is halted for this instrument"),
+            OrderError::InvalidPrice(reason) => write!(f, "Invalid price: {}", reason),
+            OrderError::PriceOffTick { tick_size } => {
+                write!(f, "Price must be a multiple of the tick size {}", tick_size)
+            }
+            OrderError::InvalidQuantity(reason) => write!(f, "Invalid quantity: {}", reason),
+            OrderError::QuantityOffLot { lot_size } => {
+                write!(f, "Quantity must be a multiple of the lot size {}", lot_size)
+            }
+            OrderError::QuantityBelowMin { min_quantity } => {
+                write!(f, "Quantity is below the minimum order size {}", min_quantity)
+            }
+            OrderError::QuantityAboveMax { max_quantity } => {
+                write!(f, "Quantity is above the maximum order size {}", max_quantity)
+            }
+            OrderError::PostOnlyWouldCross => write!(f, "Post-only order would take liquidity"),
+            OrderError::PendingStopNotAmendable => {
+                write!(f, "Pending stop orders cannot be modified; cancel and place a new one")
+            }
+            OrderError::InsufficientFunds { asset, required, available } => {
+                write!(f, "Insufficient {}: {} required, {} available", asset, required, available)
+            }
+            OrderError::UnknownAsset(asset) => write!(f, "Unknown asset {}", asset),
+            OrderError::InvalidAmount(reason) => write!(f, "Invalid amount: {}", reason),
+            OrderError::RiskMaxQuantity { limit } => {
+                write!(f, "Quantity is above the risk limit {}", limit)
+            }
+            OrderError::RiskMaxNotional { notional, limit } => {
+                write!(f, "Notional {} is above the risk limit {}", notional, limit)
+            }
+            OrderError::RiskPriceCollar { reference, collar_bps } => {
+                write!(f, "Price is more than {} bps away from {}", collar_bps, reference)
+            }
+            OrderError::RiskMaxOpenOrders { limit } => {
+                write!(f, "Already at the limit of {} open orders", limit)
+            }
+            OrderError::RiskMaxPosition { position, limit } => {
+                write!(f, "Position would reach {}, above the risk limit {}", position, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
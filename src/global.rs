@@ -1,18 +1,29 @@
-use std::sync::Mutex;
+use std::sync::{Mutex, RwLock};
 use std::{collections::HashMap, sync::Arc};
 
-use redis::Connection;
-
-use crate::inputs::{OrderBook, Symbol};
+use crate::config::APP_CONFIG;
+use crate::engine::risk::RiskEngine;
+use crate::inputs::{Accounts, Instrument, OrderBook, Symbol};
 
 lazy_static::lazy_static! {
+    /// One book per instrument in the registry, seeded from `config.yaml`.
     pub static ref ORDERBOOKS: Arc<Mutex<HashMap<Symbol, OrderBook>>> = {
-        let mut books = HashMap::new();
-        books.insert(Symbol::BTCUSD, OrderBook::new(Symbol::BTCUSD));
-        books.insert(Symbol::ETHUSD, OrderBook::new(Symbol::ETHUSD));
-        books.insert(Symbol::SOLUSD, OrderBook::new(Symbol::SOLUSD));
+        let books = APP_CONFIG.instruments.iter().map(|config| {
+            let instrument = Instrument::from_config(config)
+                .unwrap_or_else(|e| panic!("Invalid instrument {} in config.yaml: {}", config.symbol, e));
+            (instrument.symbol.clone(), OrderBook::new(instrument))
+        }).collect();
         Arc::new(Mutex::new(books))
     };
+    /// Per-user balances, seeded from the `accounts` in `config.yaml`.
+    pub static ref ACCOUNTS: Arc<Mutex<Accounts>> = {
+        let accounts = Accounts::from_config(&APP_CONFIG.accounts)
+            .unwrap_or_else(|e| panic!("Invalid accounts in config.yaml: {}", e));
+        Arc::new(Mutex::new(accounts))
+    };
+    /// Pre-trade checks with the limits from the `risk` section of
+    /// `config.yaml`, plus any registered with `add_risk_check`.
+    pub static ref RISK: RwLock<RiskEngine> = RwLock::new(RiskEngine::from_config(&APP_CONFIG.risk)
+        .unwrap_or_else(|e| panic!("Invalid risk limits in config.yaml: {}", e)));
     pub static ref NEXT_ORDER_ID: Arc<Mutex<u64>> = Arc::new(Mutex::new(1));
 }
-
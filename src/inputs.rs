@@ -1,14 +1,60 @@
-use std::collections::{BTreeMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use serde::{Deserialize, Serialize};
 
+pub use crate::units::{Amount, Decimal, Price, Quantity};
+
 #[derive(Deserialize,Serialize,Debug)]
 pub struct CreateOrderInput{
     pub symbol :Symbol,
-    pub price:f64,
-    pub quantity:u32,
+    pub price:Decimal,
+    pub quantity:Decimal,
     pub user_id:u32,
     pub side:Side,
-    pub order_type:OrderType
+    pub order_type:OrderType,
+    #[serde(default)]
+    pub time_in_force:TimeInForce,
+    /// Unix timestamp (seconds) at which a GTD order expires
+    #[serde(default)]
+    pub expire_time:Option<i64>,
+    /// Trigger price for Stop and StopLimit orders
+    #[serde(default)]
+    pub stop_price:Option<Decimal>,
+    #[serde(default)]
+    pub post_only:PostOnly,
+    /// Iceberg peak: only this much of a resting Limit order is shown in depth
+    #[serde(default)]
+    pub display_quantity:Option<Decimal>,
+    #[serde(default)]
+    pub self_trade_prevention:SelfTradePrevention,
+    /// Most quote a Market or Stop buy may spend; without it the order
+    /// reserves its quantity at the reference price plus slippage
+    #[serde(default)]
+    pub max_spend:Option<Decimal>
+}
+
+#[derive(Deserialize,Serialize,Debug)]
+pub struct CancelOrderInput{
+    pub order_id:u64
+}
+
+#[derive(Deserialize,Serialize,Debug)]
+pub struct ModifyOrderInput{
+    #[serde(default)]
+    pub order_id:u64,
+    pub price:Option<Decimal>,
+    pub quantity:Option<Decimal>
+}
+
+/// Body of a deposit or withdrawal; `user_id` is taken from the path.
+#[derive(Deserialize,Serialize,Debug)]
+pub struct TransferInput{
+    #[serde(default)]
+    pub user_id:u32,
+    /// Set by the router; recorded as the ledger reference
+    #[serde(default)]
+    pub request_id:String,
+    pub asset:String,
+    pub amount:Decimal
 }
 
 #[derive(Deserialize,Serialize,Debug,Clone, Copy,PartialEq)]
@@ -17,33 +63,506 @@ pub enum Side{
 }
 #[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq)]
 pub enum OrderType{
-    Limit,Market
+    Limit,Market,
+    /// Becomes a market order once the last trade reaches `stop_price`
+    Stop,
+    /// Becomes a limit order at `price` once the last trade reaches `stop_price`
+    StopLimit
+}
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Default)]
+pub enum TimeInForce{
+    /// Good till cancelled
+    #[default]
+    GTC,
+    /// Immediate or cancel: any unfilled remainder is cancelled
+    IOC,
+    /// Fill or kill: fills completely or not at all
+    FOK,
+    /// Good till date: rests until `expire_time`
+    GTD,
+    /// Rests until the end of the current UTC day
+    DAY
+}
+/// What to do with a maker-only limit order that would cross the spread.
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Default)]
+pub enum PostOnly{
+    #[default]
+    Off,
+    Reject,
+    /// Rest one tick behind the opposite touch instead
+    Reprice
+}
+/// How an incoming order treats resting orders from the same user.
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Default)]
+pub enum SelfTradePrevention{
+    /// Self trades are allowed
+    #[default]
+    Off,
+    /// Cancel the remainder of the incoming order
+    CancelNewest,
+    /// Cancel the resting order and keep matching
+    CancelOldest,
+    CancelBoth,
+    /// Reduce both orders by the smaller quantity, cancelling whichever reaches zero
+    DecrementAndCancel
+}
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq)]
+pub enum OrderStatus{
+    /// Stop order waiting for its trigger price
+    Pending,
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Killed,
+    /// Post-only order that would have taken liquidity
+    Rejected
 }
-#[derive(Deserialize,Serialize,Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Symbol {
-    BTCUSD,
-    ETHUSD,
-    SOLUSD
+/// Instrument name such as `BTCUSD`; valid names are whatever the registry holds.
+#[derive(Deserialize,Serialize,Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Symbol(pub String);
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Default)]
+pub enum InstrumentStatus{
+    #[default]
+    Trading,
+    /// No new orders or amendments; resting orders can still be cancelled
+    Halted
+}
+pub type OrderId = u64;
+
+/// An `instruments` entry in `config.yaml`, and the body of the admin add request.
+/// Price and quantity precision follow the decimals of `tick_size` and `lot_size`.
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct InstrumentConfig{
+    pub symbol:Symbol,
+    pub base_asset:String,
+    pub quote_asset:String,
+    pub tick_size:Decimal,
+    pub lot_size:Decimal,
+    /// Defaults to `lot_size`
+    #[serde(default)]
+    pub min_quantity:Option<Decimal>,
+    pub max_quantity:Decimal,
+    #[serde(default)]
+    pub status:InstrumentStatus,
+    #[serde(default)]
+    pub fees:FeeScheduleConfig
+}
+
+/// Fees in basis points of what each side of a fill receives. A negative
+/// maker rate is a rebate, paid in the asset the taker received and no larger
+/// than the lowest taker rate.
+#[derive(Deserialize,Serialize,Debug,Clone,Default,PartialEq)]
+pub struct FeeScheduleConfig{
+    #[serde(default)]
+    pub maker_bps:i32,
+    #[serde(default)]
+    pub taker_bps:i32,
+    /// Rates for users whose trailing 30-day volume reaches `min_volume`
+    #[serde(default)]
+    pub tiers:Vec<FeeTierConfig>
+}
+
+#[derive(Deserialize,Serialize,Debug,Clone,PartialEq)]
+pub struct FeeTierConfig{
+    /// Notional traded across all instruments with the same quote asset
+    pub min_volume:Decimal,
+    pub maker_bps:i32,
+    pub taker_bps:i32
+}
+
+/// Pre-trade limits; any left unset is not checked. Quantities and positions
+/// are in the base asset, notionals in the quote asset.
+#[derive(Deserialize,Serialize,Debug,Clone,Default)]
+pub struct RiskLimitsConfig{
+    #[serde(default)]
+    pub max_order_quantity:Option<Decimal>,
+    #[serde(default)]
+    pub max_order_notional:Option<Decimal>,
+    /// Furthest a limit price may be from the collar reference, in basis points
+    #[serde(default)]
+    pub price_collar_bps:Option<u32>,
+    #[serde(default)]
+    pub collar_reference:Option<CollarReference>,
+    /// Resting and untriggered stop orders per user in one book
+    #[serde(default)]
+    pub max_open_orders:Option<usize>,
+    /// Most base asset a user may hold once their open buys fill
+    #[serde(default)]
+    pub max_position:Option<Decimal>,
+}
+
+/// Price the collar is measured from. Falls back to the other one when the
+/// book has no such price yet.
+#[derive(Deserialize,Serialize,Debug,Clone,Copy,Default,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollarReference{
+    #[default]
+    LastTrade,
+    Mid
+}
+
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct SymbolRiskConfig{
+    pub symbol:Symbol,
+    #[serde(flatten)]
+    pub limits:RiskLimitsConfig
+}
+
+/// Limits for one user, in every book or only in `symbol`.
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct UserRiskConfig{
+    pub user_id:u32,
+    #[serde(default)]
+    pub symbol:Option<Symbol>,
+    #[serde(flatten)]
+    pub limits:RiskLimitsConfig
+}
+
+/// The `risk` section of `config.yaml`. More specific entries override
+/// `defaults` limit by limit: symbol, then user, then user in a symbol.
+#[derive(Deserialize,Serialize,Debug,Clone,Default)]
+pub struct RiskConfig{
+    #[serde(default)]
+    pub defaults:RiskLimitsConfig,
+    #[serde(default)]
+    pub symbols:Vec<SymbolRiskConfig>,
+    #[serde(default)]
+    pub users:Vec<UserRiskConfig>
+}
+
+/// An `accounts` entry in `config.yaml`: starting balances for one user.
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct AccountConfig{
+    pub user_id:u32,
+    /// Asset name to decimal amount
+    pub balances:BTreeMap<String, Decimal>
+}
+
+#[derive(Deserialize,Serialize,Debug)]
+pub struct InstrumentStatusInput{
+    #[serde(default)]
+    pub symbol:Symbol,
+    pub status:InstrumentStatus
+}
+
+/// Per-symbol trading rules. Prices and quantities are integers in units of
+/// `10^-price_decimals` and `10^-quantity_decimals`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstrumentSpec {
+    pub price_decimals: u32,
+    pub quantity_decimals: u32,
+    pub tick_size: Price,
+    pub lot_size: Quantity,
+    pub min_quantity: Quantity,
+    pub max_quantity: Quantity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: Symbol,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub spec: InstrumentSpec,
+    pub status: InstrumentStatus,
+    /// Fee rates by trailing volume, starting with the base rates at zero volume
+    pub fee_tiers: Vec<FeeTier>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FeeTier {
+    /// Trailing 30-day volume in the quote asset
+    pub min_volume: Amount,
+    pub maker_bps: i32,
+    pub taker_bps: i32,
+}
+
+/// What one side of a fill was charged, taken from the asset it received. A
+/// maker rebate is paid in the asset the taker received instead.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Fee {
+    pub asset: String,
+    pub bps: i32,
+    /// Negative for a rebate
+    pub amount: i64,
 }
-type OrderId = u64;
-pub type Price = u64;
-type Quantity = u64;
 
 #[derive(Debug, Clone,Serialize,Deserialize)]
 pub struct Order {
     pub id: OrderId,
+    pub user_id: u32,
     pub price: Price,
+    /// Displayed quantity; the whole remaining quantity unless the order is an iceberg
     pub qty: Quantity,
+    /// Iceberg peak size used to refill `qty` from `hidden_qty`
+    pub display_qty: Option<Quantity>,
+    /// Iceberg reserve not shown in depth
+    pub hidden_qty: Quantity,
     pub is_buy: bool,
     pub order_type:OrderType,
-    pub time:String,
+    pub stop_price:Option<Price>,
+    pub post_only:PostOnly,
+    pub self_trade_prevention:SelfTradePrevention,
+    pub time_in_force:TimeInForce,
+    pub expires_at:Option<i64>,
+    /// Unix nanoseconds the order was accepted or last requeued at
+    pub time:i64,
+    /// Quote funds a market-priced buy may spend; matching stops before exceeding them
+    #[serde(default)]
+    pub budget:Option<Amount>,
 }
 
-#[derive(Debug)]
-pub struct OrderBook {
-    pub bids: BTreeMap<Price, Vec<Order>>, 
-    pub asks: BTreeMap<Price, Vec<Order>>, 
+#[derive(Debug, Clone,Serialize,Deserialize)]
+pub struct Trade {
+    pub trade_id: u64,
+    pub symbol: Symbol,
+    pub price: Price,
+    pub qty: Quantity,
+    pub maker_order_id: OrderId,
+    pub taker_order_id: OrderId,
+    pub maker_user_id: u32,
+    pub taker_user_id: u32,
+    pub aggressor_side: Side,
+    /// Unix nanoseconds
+    pub time: i64,
+    /// Filled in when the trade is settled
+    #[serde(default)]
+    pub maker_fee: Fee,
+    #[serde(default)]
+    pub taker_fee: Fee,
+}
+
+/// Quantity removed from an order by self-trade prevention instead of trading.
+#[derive(Debug, Clone,Serialize,Deserialize)]
+pub struct SelfTradePrevented {
+    pub order_id: OrderId,
+    pub user_id: u32,
+    pub cancelled_qty: Quantity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookEventKind {
+    /// Order (or an iceberg's refilled slice) joined the back of its level
+    Add,
+    /// Displayed quantity reduced in place, keeping queue priority
+    Modify,
+    Cancel,
+    /// Resting order traded; it leaves the book once its quantity reaches zero
+    Execute,
+}
+
+/// One order-level change to the visible book. `qty` is the order's new displayed
+/// quantity for add/modify, the quantity removed for cancel and the fill size for
+/// execute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookEvent {
+    /// Per-symbol sequence, assigned when the event is drained from the book
+    pub seq: u64,
+    pub kind: BookEventKind,
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub qty: Quantity,
+    pub trade_id: Option<u64>,
+}
+
+/// Events published together on a symbol's `book_events:{symbol}` channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookEventBatch {
+    pub symbol: Symbol,
+    pub events: Vec<BookEvent>,
+}
+
+/// Every order resting in a book, in priority order on each side, as of the
+/// last L3 event the worker has published.
+#[derive(Debug, Clone)]
+pub struct BookOrders {
+    pub symbol: Symbol,
+    pub spec: InstrumentSpec,
+    /// `seq` of the last event the orders reflect
+    pub seq: u64,
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub enum CandleInterval{
+    #[serde(rename = "1s")]
+    S1,
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "15m")]
+    M15,
+    #[serde(rename = "1h")]
+    H1,
+    #[serde(rename = "1d")]
+    D1
+}
+
+/// Query string of `GET /candles`; `from` and `to` are unix seconds bounding `open_time`.
+#[derive(Deserialize,Serialize,Debug)]
+pub struct CandleQuery{
     pub symbol:Symbol,
+    pub interval:CandleInterval,
+    pub from:Option<i64>,
+    pub to:Option<i64>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candle {
+    pub symbol: Symbol,
+    pub interval: CandleInterval,
+    /// Unix seconds at the start of the bar
+    pub open_time: i64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Quantity,
+    pub trade_count: u64,
+    /// No more trades will be added
+    pub closed: bool,
+}
+
+/// Most recent candles per symbol and interval, oldest first. The last bar of a
+/// series is the in-progress one until it is closed.
+#[derive(Debug, Default)]
+pub struct CandleStore {
+    pub series: HashMap<(Symbol, CandleInterval), VecDeque<Candle>>,
+    pub max_candles: usize,
+}
+
+/// One user's funds in one asset. `locked` is reserved for open orders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: Amount,
+    pub locked: Amount,
+}
+
+/// Funds still reserved for one open order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderLock {
+    pub user_id: u32,
+    pub asset: String,
+    pub amount: Amount,
+}
+
+/// Balances of every user, by user id then asset, and what each open order has reserved.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Accounts {
+    pub balances: BTreeMap<u32, BTreeMap<String, Balance>>,
+    pub order_locks: HashMap<OrderId, OrderLock>,
+    /// Notional traded by user, quote asset and UTC day, over the days fee tiers look back
+    pub volumes: BTreeMap<u32, BTreeMap<String, BTreeMap<i64, Amount>>>,
+    /// What fills paid beyond a user's funds, by user id then asset; any entry is a bug `validate` reports
+    #[serde(default)]
+    pub overdrafts: BTreeMap<u32, BTreeMap<String, Amount>>,
+    /// Fees collected less rebates paid, by asset: what the `Fees` ledger account holds
+    pub fee_pool: BTreeMap<String, Amount>,
+    /// Sequence number the next ledger entry gets
+    pub next_ledger_seq: u64,
+    /// Ledger entries not yet drained by the worker
+    #[serde(skip)]
+    pub ledger_entries: Vec<LedgerEntry>,
+}
+
+/// Who a ledger posting moves funds for. User accounts hold what the exchange
+/// owes each user; `External` is the other side of deposits and withdrawals
+/// and `Fees` collects trading fees and pays maker rebates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    User(u32),
+    External,
+    Fees,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntrySide {
+    /// Takes funds out of the account
+    Debit,
+    /// Puts funds into the account
+    Credit,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub asset: String,
+    pub side: EntrySide,
+    pub amount: Amount,
+}
+
+/// One balance movement. Debits and credits balance for every asset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub seq: u64,
+    /// Unix nanoseconds
+    pub timestamp: i64,
+    /// What caused the movement, e.g. `trade:BTCUSD:42` or `deposit:{request_id}`
+    pub reference: String,
+    pub postings: Vec<Posting>,
+}
+
+/// Time-ordered queue of the orders resting at one price. Removed slots are
+/// left as `None` until they reach either end of the queue or, once they make
+/// up half of it, the level is compacted. Positions handed out by `push` stay
+/// valid until the order leaves the level or the level is compacted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub orders: VecDeque<Option<Order>>,
+    pub head: u64,
+    /// Removed slots still in `orders`
+    #[serde(default)]
+    pub tombstones: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OrderLocation {
+    pub is_buy: bool,
+    /// Waiting in `buy_stops`/`sell_stops` rather than resting in the book
+    pub is_stop: bool,
+    pub price: Price,
+    pub position: u64,
+}
+
+/// What one user has open in a book, kept up to date for the risk checks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserOrders {
+    /// Resting and untriggered stop orders
+    pub open: usize,
+    /// Total quantity, hidden included, of those that buy
+    pub buy_qty: Quantity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBook {
+    pub bids: BTreeMap<Price, PriceLevel>, 
+    pub asks: BTreeMap<Price, PriceLevel>, 
+    /// Untriggered stop orders keyed by stop price
+    pub buy_stops: BTreeMap<Price, PriceLevel>,
+    pub sell_stops: BTreeMap<Price, PriceLevel>,
+    pub order_index: HashMap<OrderId, OrderLocation>,
+    pub expiries: BTreeSet<(i64, OrderId)>,
+    pub user_orders: HashMap<u32, UserOrders>,
+    /// Self-trade cancellations since the last drain by `process_order`/`process_modify`
+    #[serde(skip)]
+    pub self_trades_prevented: Vec<SelfTradePrevented>,
+    /// L3 events not yet drained by the worker
+    #[serde(skip)]
+    pub book_events: Vec<BookEvent>,
+    /// Orders that may have left the book since the last drain, so their
+    /// reserved funds can be released
+    #[serde(skip)]
+    pub closed_orders: Vec<OrderId>,
+    pub next_event_seq: u64,
+    pub instrument: Instrument,
+    pub next_trade_id: u64,
     pub current_price:Option<Price>,
     pub last_trade_price: Option<Price>,
     pub current_best_bid: Option<Price>,
@@ -52,22 +571,94 @@ pub struct OrderBook {
 
 #[derive(Debug)]
 pub struct OrderBookDepth {
-    pub bids: Vec<(u64, u64)>, 
-    pub asks: Vec<(u64, u64)>, 
+    pub bids: Vec<(Price, Quantity)>, 
+    pub asks: Vec<(Price, Quantity)>, 
+}
+
+/// Every displayed price level of one book, as published by the worker.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookDepth {
+    pub symbol: Symbol,
+    /// Number of updates published for this book since the worker started
+    pub seq: u64,
+    #[serde(with = "crate::units::level_pairs")]
+    pub bids: BTreeMap<Price, Quantity>,
+    #[serde(with = "crate::units::level_pairs")]
+    pub asks: BTreeMap<Price, Quantity>,
+    pub last_trade_price: Option<Price>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LevelChange {
+    pub side: Side,
+    pub price: Price,
+    /// New aggregate displayed quantity; zero removes the level
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthUpdate {
+    pub symbol: Symbol,
+    pub seq: u64,
+    pub changes: Vec<LevelChange>,
+    pub last_trade_price: Option<Price>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub instrument: InstrumentConfig,
+    pub depth: BookDepth,
+}
+
+/// Messages on the worker's `depth_updates` channel. An update applies only on
+/// top of the snapshot or update with the previous `seq`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DepthFeedMessage {
+    Snapshot(Box<DepthSnapshot>),
+    Update(DepthUpdate),
 }
 
 #[derive(Debug)]
 pub struct ProcessOrderResult {
     pub order_id: u64,
-    pub trades: Vec<Order>,
-    pub remaining_quantity: u64,
+    pub status: OrderStatus,
+    pub time_in_force: TimeInForce,
+    /// Price a post-only Reprice order was moved to
+    pub repriced_price: Option<Price>,
+    pub trades: Vec<Trade>,
+    pub self_trades_prevented: Vec<SelfTradePrevented>,
+    pub remaining_quantity: Quantity,
+    pub orderbook_state: OrderBookState,
+}
+#[derive(Debug)]
+pub struct CancelOrderResult {
+    pub order_id: u64,
+    pub cancelled_quantity: Quantity,
+    pub orderbook_state: OrderBookState,
+}
+#[derive(Debug)]
+pub struct TransferResult {
+    pub user_id: u32,
+    pub asset: String,
+    /// Balance after the transfer
+    pub balance: Balance,
+}
+#[derive(Debug)]
+pub struct ModifyOrderResult {
+    pub order_id: u64,
+    pub trades: Vec<Trade>,
+    pub self_trades_prevented: Vec<SelfTradePrevented>,
+    pub remaining_quantity: Quantity,
     pub orderbook_state: OrderBookState,
 }
 #[derive(Debug)]
 pub struct OrderBookState {
     pub symbol: Symbol,
-    pub current_price: Option<u64>,
-    pub best_bid: Option<u64>,
-    pub best_ask: Option<u64>,
-    pub last_trade_price: Option<u64>,
+    /// Units of the prices below
+    pub spec: InstrumentSpec,
+    pub current_price: Option<Price>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub last_trade_price: Option<Price>,
 }
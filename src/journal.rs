@@ -0,0 +1,220 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::clock::ManualClock;
+
+/// One command as the worker received it, with the time it was applied at so
+/// replay makes the same time-dependent decisions (GTD checks, DAY expiry, sweeps).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Unix nanoseconds
+    pub timestamp: i64,
+    pub command: Value,
+}
+
+impl JournalEntry {
+    /// A clock stopped at the time the command was applied, to apply it again with.
+    pub fn clock(&self) -> ManualClock {
+        ManualClock::new(self.timestamp)
+    }
+}
+
+/// What `Journal::read` found on disk.
+#[derive(Debug, Default)]
+pub struct JournalContents {
+    /// Journal offset of the first record in the file
+    pub base: u64,
+    /// Every intact record with the offset it starts at
+    pub entries: Vec<(u64, JournalEntry)>,
+    /// Offset just past the last intact record
+    pub end: u64,
+}
+
+/// Append-only log of every state-changing command, fsync'd before the
+/// command is applied.
+///
+/// The file starts with the journal offset of its first record (`u64 LE`), so
+/// offsets stay meaningful after the records a snapshot covers are truncated.
+/// Each record is `[len: u32 LE][crc32 of payload: u32 LE][payload: JSON JournalEntry]`.
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    /// Journal offset just past the last record
+    offset: u64,
+}
+
+const BASE_LEN: usize = 8;
+const HEADER_LEN: usize = 8;
+
+impl Journal {
+    /// Opens the journal for appending, creating it and its directory if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        if fs::metadata(&path).is_err() {
+            write_empty(&path, 0)?;
+        }
+
+        let JournalContents { base, end, .. } = Self::read(&path)?;
+        if fs::metadata(&path)?.len() != BASE_LEN as u64 + (end - base) {
+            return Err(format!("Journal {} ends in a torn record; recover it first", path.display()).into());
+        }
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self { file, path, offset: end })
+    }
+
+    /// Journal offset the next record will be written at.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), Box<dyn std::error::Error>> {
+        let payload = serde_json::to_vec(entry)?;
+        let len = u32::try_from(payload.len()).map_err(|_| "Journal record too large")?;
+
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.offset += record.len() as u64;
+        Ok(())
+    }
+
+    /// Drops every record written so far. The replacement file keeps the
+    /// current offset as its base and is renamed into place, so a crash leaves
+    /// either the old journal or the new one.
+    pub fn truncate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        write_empty(&self.path, self.offset)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Reads every intact record. Reading stops at the first short or corrupt
+    /// record, which is what a crash in the middle of an append leaves behind.
+    pub fn read(path: impl AsRef<Path>) -> Result<JournalContents, Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        match File::open(path.as_ref()) {
+            Ok(mut file) => {
+                file.read_to_end(&mut bytes)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(JournalContents::default()),
+            Err(e) => return Err(e.into()),
+        }
+        let base = u64::from_le_bytes(
+            bytes.get(..BASE_LEN).ok_or("Journal is missing its header")?.try_into()?,
+        );
+
+        let mut entries = Vec::new();
+        let mut pos = BASE_LEN;
+        while let Some(header) = bytes.get(pos..pos + HEADER_LEN) {
+            let len = u32::from_le_bytes(header[..4].try_into()?) as usize;
+            let checksum = u32::from_le_bytes(header[4..].try_into()?);
+            let Some(payload) = bytes.get(pos + HEADER_LEN..pos + HEADER_LEN + len) else {
+                break;
+            };
+            if crc32fast::hash(payload) != checksum {
+                break;
+            }
+            let Ok(entry) = serde_json::from_slice(payload) else {
+                break;
+            };
+            entries.push((base + (pos - BASE_LEN) as u64, entry));
+            pos += HEADER_LEN + len;
+        }
+        Ok(JournalContents { base, entries, end: base + (pos - BASE_LEN) as u64 })
+    }
+
+    /// Reads the records from journal offset `from` onwards and cuts off any
+    /// torn record at the end, so new appends follow the last intact one.
+    pub fn recover(path: impl AsRef<Path>, from: u64) -> Result<Vec<JournalEntry>, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let JournalContents { base, entries, end } = Self::read(path)?;
+        if from < base || from > end {
+            return Err(format!(
+                "Journal {} holds offsets {}..{}, cannot resume from {}",
+                path.display(),
+                base,
+                end,
+                from
+            )
+            .into());
+        }
+
+        if let Ok(metadata) = fs::metadata(path)
+            && metadata.len() > BASE_LEN as u64 + (end - base)
+        {
+            eprintln!(
+                "Journal {} has {} unreadable trailing bytes, truncating",
+                path.display(),
+                metadata.len() - BASE_LEN as u64 - (end - base)
+            );
+            OpenOptions::new().write(true).open(path)?.set_len(BASE_LEN as u64 + (end - base))?;
+        }
+        Ok(entries
+            .into_iter()
+            .filter(|(offset, _)| *offset >= from)
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+}
+
+/// Atomically replaces `path` with a journal that has no records and starts at `base`.
+fn write_empty(path: &Path, base: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&base.to_le_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("orderbook-journal-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("orderbook.journal")
+    }
+
+    #[test]
+    fn recovered_entries_replay_at_the_time_they_were_applied() {
+        let path = temp_path("times");
+        let mut journal = Journal::open(&path).unwrap();
+        for (timestamp, order_id) in [(1_000, 1), (2_000, 2)] {
+            journal.append(&JournalEntry { timestamp, command: serde_json::json!({ "type": "cancel", "order_id": order_id }) }).unwrap();
+        }
+
+        let entries = Journal::recover(&path, 0).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.timestamp).collect::<Vec<_>>(), vec![1_000, 2_000]);
+        assert_eq!(entries[1].command["order_id"], 2);
+    }
+
+    #[test]
+    fn a_torn_record_is_cut_off() {
+        let path = temp_path("torn");
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&JournalEntry { timestamp: 1, command: serde_json::json!({ "type": "expire" }) }).unwrap();
+        let intact = journal.offset();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[7, 0, 0, 0, 1]).unwrap();
+
+        assert_eq!(Journal::recover(&path, 0).unwrap().len(), 1);
+        assert_eq!(Journal::read(&path).unwrap().end, intact);
+        assert_eq!(fs::metadata(&path).unwrap().len(), BASE_LEN as u64 + intact);
+    }
+}
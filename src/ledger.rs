@@ -0,0 +1,219 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+};
+
+use crate::inputs::{Accounts, EntrySide, LedgerAccount, LedgerEntry};
+
+/// Append-only record of every balance movement, one JSON `LedgerEntry` per
+/// line, fsync'd after each append. Entries are numbered from 0 with no gaps.
+pub struct Ledger {
+    file: File,
+    next_seq: u64,
+}
+
+impl Ledger {
+    /// Opens the ledger for appending, creating it and its directory if needed.
+    /// A torn line left by a crash in the middle of an append is cut off.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let (entries, intact_len) = read_entries(path)?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() > intact_len {
+            eprintln!("Ledger {} ends in a torn entry, truncating", path.display());
+            file.set_len(intact_len)?;
+        }
+        let next_seq = entries.last().map(|entry| entry.seq + 1).unwrap_or(0);
+        Ok(Self { file, next_seq })
+    }
+
+    /// Sequence number of the next entry the ledger expects.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Writes the entries the ledger does not hold yet. Entries it already
+    /// holds, such as those regenerated by journal replay after a restart, are
+    /// skipped; a gap means entries were lost and is an error.
+    pub fn append(&mut self, entries: &[LedgerEntry]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut lines = Vec::new();
+        let mut next_seq = self.next_seq;
+        for entry in entries.iter().filter(|entry| entry.seq >= self.next_seq) {
+            if entry.seq != next_seq {
+                return Err(format!("Ledger expects entry {} but got {}", next_seq, entry.seq).into());
+            }
+            serde_json::to_writer(&mut lines, entry)?;
+            lines.push(b'\n');
+            next_seq += 1;
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(&lines)?;
+        self.file.sync_data()?;
+        self.next_seq = next_seq;
+        Ok(())
+    }
+
+    /// Every intact entry, in order.
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<LedgerEntry>, Box<dyn std::error::Error>> {
+        Ok(read_entries(path.as_ref())?.0)
+    }
+}
+
+/// Entries up to the first torn or unreadable line, and the byte length they take up.
+fn read_entries(path: &Path) -> Result<(Vec<LedgerEntry>, u64), Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut bytes)?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while let Some(len) = bytes[pos..].iter().position(|b| *b == b'\n') {
+        let Ok(entry) = serde_json::from_slice(&bytes[pos..pos + len]) else {
+            break;
+        };
+        entries.push(entry);
+        pos += len + 1;
+    }
+    Ok((entries, pos as u64))
+}
+
+/// Recomputes every user's balances from the ledger and compares them with
+/// `accounts`. Also checks that the entries are numbered without gaps, that
+/// each one balances per asset and that none are missing from the end.
+/// Empty when the ledger and the accounts agree.
+pub fn audit(entries: &[LedgerEntry], accounts: &Accounts) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut totals: BTreeMap<(u32, &str), i128> = BTreeMap::new();
+
+    for (expected_seq, entry) in (0..).zip(entries) {
+        if entry.seq != expected_seq {
+            problems.push(format!("entry {} is numbered {}", expected_seq, entry.seq));
+        }
+        let mut net: BTreeMap<&str, i128> = BTreeMap::new();
+        for posting in &entry.postings {
+            let amount = match posting.side {
+                EntrySide::Credit => posting.amount.0 as i128,
+                EntrySide::Debit => -(posting.amount.0 as i128),
+            };
+            *net.entry(&posting.asset).or_default() += amount;
+            if let LedgerAccount::User(user_id) = posting.account {
+                *totals.entry((user_id, &posting.asset)).or_default() += amount;
+            }
+        }
+        for (asset, net) in net.into_iter().filter(|(_, net)| *net != 0) {
+            problems.push(format!("entry {} ({}) is off by {} {}", entry.seq, entry.reference, net, asset));
+        }
+    }
+    if entries.len() as u64 != accounts.next_ledger_seq {
+        problems.push(format!(
+            "ledger holds {} entries but the accounts have recorded {}",
+            entries.len(),
+            accounts.next_ledger_seq
+        ));
+    }
+
+    for (user_id, assets) in &accounts.balances {
+        for (asset, balance) in assets {
+            let live = balance.available.0 as i128 + balance.locked.0 as i128;
+            let recorded = totals.remove(&(*user_id, asset.as_str())).unwrap_or_default();
+            if live != recorded {
+                problems.push(format!("user {} {}: ledger says {} but the account holds {}", user_id, asset, recorded, live));
+            }
+        }
+    }
+    for ((user_id, asset), recorded) in totals.into_iter().filter(|(_, recorded)| *recorded != 0) {
+        problems.push(format!("user {} {}: ledger says {} but the account holds nothing", user_id, asset, recorded));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::{NOW, fill, instrument};
+    use crate::inputs::Amount;
+
+    const UNIT: u64 = 100_000_000;
+
+    /// Deposits for a buyer and a seller, a trade between them and a withdrawal.
+    fn accounts() -> Accounts {
+        let mut accounts = Accounts::default();
+        accounts.deposit(1, "BTC", Amount(2 * UNIT), NOW, "deposit:a".to_string()).unwrap();
+        accounts.deposit(2, "USD", Amount(500 * UNIT), NOW, "deposit:b".to_string()).unwrap();
+        accounts.settle(&mut fill(1, 2, 10_000, 1_000, NOW), &instrument());
+        accounts.withdraw(1, "USD", Amount(40 * UNIT), NOW, "withdraw:c".to_string()).unwrap();
+        accounts
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("orderbook-ledger-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("ledger.jsonl")
+    }
+
+    #[test]
+    fn a_complete_ledger_audits_clean() {
+        let mut accounts = accounts();
+        let entries = accounts.drain_ledger_entries();
+        assert_eq!(entries.iter().map(|entry| entry.reference.as_str()).collect::<Vec<_>>(), vec!["deposit:a", "deposit:b", "trade:BTCUSD:1", "withdraw:c"]);
+        assert!(audit(&entries, &accounts).is_empty());
+    }
+
+    #[test]
+    fn audit_reports_unbalanced_missing_and_divergent_entries() {
+        let mut accounts = accounts();
+        let mut entries = accounts.drain_ledger_entries();
+        entries[0].postings[1].amount = Amount(3 * UNIT);
+        entries.pop();
+
+        let problems = audit(&entries, &accounts);
+        assert!(problems.contains(&format!("entry 0 (deposit:a) is off by {} BTC", UNIT)));
+        assert!(problems.contains(&"ledger holds 3 entries but the accounts have recorded 4".to_string()));
+        assert!(problems.contains(&format!("user 1 BTC: ledger says {} but the account holds {}", 2 * UNIT, UNIT)));
+        assert!(problems.contains(&format!("user 1 USD: ledger says {} but the account holds {}", 100 * UNIT, 60 * UNIT)));
+    }
+
+    #[test]
+    fn append_skips_held_entries_and_rejects_gaps() {
+        let path = temp_path("append");
+        let entries = accounts().drain_ledger_entries();
+        let mut ledger = Ledger::open(&path).unwrap();
+        ledger.append(&entries[..2]).unwrap();
+        // Replay after a restart regenerates entries the ledger already holds
+        ledger.append(&entries[1..3]).unwrap();
+        assert!(ledger.append(&entries[..0]).is_ok());
+
+        assert_eq!(Ledger::read(&path).unwrap().iter().map(|entry| entry.seq).collect::<Vec<_>>(), vec![0, 1, 2]);
+        let mut ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.next_seq(), 3);
+        let mut gap = entries[3].clone();
+        gap.seq = 5;
+        assert!(ledger.append(&[gap]).is_err());
+    }
+
+    #[test]
+    fn a_torn_line_is_cut_off_on_open() {
+        let path = temp_path("torn");
+        let entries = accounts().drain_ledger_entries();
+        Ledger::open(&path).unwrap().append(&entries[..1]).unwrap();
+        let intact = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"seq\":1,").unwrap();
+
+        assert_eq!(Ledger::open(&path).unwrap().next_seq(), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+    }
+}
@@ -1,7 +1,13 @@
 pub mod inputs;
 pub mod output;
 pub mod router;
-pub use config::*;
+pub mod config;
 pub mod sim;
 pub mod engine;
-pub mod global;
\ No newline at end of file
+pub mod global;
+pub mod units;
+pub mod clock;
+pub mod error;
+pub mod journal;
+pub mod ledger;
+pub mod snapshot;
\ No newline at end of file
@@ -5,6 +5,7 @@ pub mod router;
 pub mod inputs;
 pub mod output;
 pub mod config;
+pub mod units;
 
 use config::APP_CONFIG;
 #[actix_web::main]
@@ -28,5 +29,5 @@ async fn main() -> Result<(),std::io::Error>{
 }
 #[get("/")]
 async fn base() ->impl Responder{
-    return "Hello world";
+    "Hello world"
 }
\ No newline at end of file
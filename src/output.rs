@@ -1,11 +1,86 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::inputs::{CandleInterval, InstrumentConfig, Symbol};
+
 #[derive(Deserialize,Serialize)]
 pub struct CreateOrderOutput{
     pub success:Success,
-    pub order_id:u32
+    pub order_id:u32,
+    pub status:String,
+    /// Price a post-only Reprice order was moved to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repriced_price:Option<String>,
+    pub remaining_quantity:String,
+    /// Net fees charged to the order across its fills, by asset; negative for rebates
+    pub fees:BTreeMap<String,String>
 }
 #[derive(Deserialize,Serialize)]
 pub enum Success{
     True,False
-}
\ No newline at end of file
+}
+#[derive(Deserialize,Serialize)]
+pub struct CancelOrderOutput{
+    pub success:Success,
+    pub order_id:u64,
+    pub cancelled_quantity:String
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct ErrorOutput{
+    pub success:Success,
+    pub error:String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code:Option<String>
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct ModifyOrderOutput{
+    pub success:Success,
+    pub order_id:u64,
+    pub filled_quantity:String,
+    pub remaining_quantity:String
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct InstrumentOutput{
+    pub success:Success,
+    pub instrument:InstrumentConfig
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct InstrumentListOutput{
+    pub success:Success,
+    pub instruments:Vec<InstrumentConfig>
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct AssetBalance{
+    pub asset:String,
+    pub available:String,
+    pub locked:String
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct BalanceOutput{
+    pub success:Success,
+    pub user_id:u32,
+    #[serde(flatten)]
+    pub balance:AssetBalance
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct BalancesOutput{
+    pub success:Success,
+    pub user_id:u32,
+    pub balances:Vec<AssetBalance>
+}
+
+#[derive(Deserialize,Serialize)]
+pub struct CandlesOutput{
+    pub success:Success,
+    pub symbol:Symbol,
+    pub interval:CandleInterval,
+    pub candles:Vec<serde_json::Value>
+}
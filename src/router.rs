@@ -1,46 +1,266 @@
-use actix_web::{post, web::{self, Data, Json}, HttpResponse, Responder};
-use serde_json::Value;
+use actix_web::{delete, get, patch, post, web::{self, Data, Json, Path, Query}, HttpResponse, Responder};
+use serde_json::{json, Value};
 use redis::AsyncCommands;
 use futures_util::stream::StreamExt;
 use uuid::Uuid;
-use crate::{inputs::CreateOrderInput, output::{CreateOrderOutput, Success}};
+use crate::{inputs::{CandleQuery, CreateOrderInput, InstrumentConfig, InstrumentStatusInput, ModifyOrderInput, TransferInput}, output::{BalanceOutput, BalancesOutput, CancelOrderOutput, CandlesOutput, CreateOrderOutput, ErrorOutput, InstrumentListOutput, InstrumentOutput, ModifyOrderOutput, Success}};
 
 type RedisPool = redis::Client;
 
 #[post("/order")]
 pub async fn create_order(body:Json<CreateOrderInput>,redis_client:Data<RedisPool>) ->impl Responder{
 
+    let order = serde_json::to_value(&body.0).unwrap();
+    let v = match send_request(&redis_client, order).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    if let Some(error) = v["error"].as_str() {
+        return HttpResponse::BadRequest().json(ErrorOutput{
+            success:Success::False,
+            error:error.to_string(),
+            code:v["code"].as_str().map(str::to_string)
+        });
+    }
+    HttpResponse::Ok().json(CreateOrderOutput{
+        success:Success::True,
+        order_id:v["result_id"].as_u64().unwrap_or(0) as u32,
+        status:v["status"].as_str().unwrap_or_default().to_string(),
+        repriced_price:v["repriced_price"].as_str().map(str::to_string),
+        remaining_quantity:v["remaining_quantity"].as_str().unwrap_or("0").to_string(),
+        fees:serde_json::from_value(v["fees"].clone()).unwrap_or_default()
+    })
+}
+
+#[delete("/order/{order_id}")]
+pub async fn cancel_order(path:Path<u64>,redis_client:Data<RedisPool>) ->impl Responder{
+
+    let order_id = path.into_inner();
+    let cancel = json!({
+        "type": "cancel",
+        "order_id": order_id
+    });
+    let v = match send_request(&redis_client, cancel).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    if let Some(error) = v["error"].as_str() {
+        return HttpResponse::NotFound().json(ErrorOutput{
+            success:Success::False,
+            error:error.to_string(),
+            code:v["code"].as_str().map(str::to_string)
+        });
+    }
+    HttpResponse::Ok().json(CancelOrderOutput{
+        success:Success::True,
+        order_id,
+        cancelled_quantity:v["cancelled_quantity"].as_str().unwrap_or("0").to_string()
+    })
+}
+
+#[patch("/order/{order_id}")]
+pub async fn modify_order(path:Path<u64>,body:Json<ModifyOrderInput>,redis_client:Data<RedisPool>) ->impl Responder{
+
+    let order_id = path.into_inner();
+    let mut modify = serde_json::to_value(&body.0).unwrap();
+    modify["type"] = Value::String("modify".to_string());
+    modify["order_id"] = json!(order_id);
+    let v = match send_request(&redis_client, modify).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    if let Some(error) = v["error"].as_str() {
+        return HttpResponse::BadRequest().json(ErrorOutput{
+            success:Success::False,
+            error:error.to_string(),
+            code:v["code"].as_str().map(str::to_string)
+        });
+    }
+    HttpResponse::Ok().json(ModifyOrderOutput{
+        success:Success::True,
+        order_id,
+        filled_quantity:v["filled_quantity"].as_str().unwrap_or("0").to_string(),
+        remaining_quantity:v["remaining_quantity"].as_str().unwrap_or("0").to_string()
+    })
+}
+
+#[get("/admin/instruments")]
+pub async fn list_instruments(redis_client:Data<RedisPool>) ->impl Responder{
+
+    let v = match send_request(&redis_client, json!({ "type": "list_instruments" })).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    HttpResponse::Ok().json(InstrumentListOutput{
+        success:Success::True,
+        instruments:serde_json::from_value(v["instruments"].clone()).unwrap_or_default()
+    })
+}
+
+#[post("/admin/instruments")]
+pub async fn add_instrument(body:Json<InstrumentConfig>,redis_client:Data<RedisPool>) ->impl Responder{
+
+    let mut instrument = serde_json::to_value(&body.0).unwrap();
+    instrument["type"] = Value::String("add_instrument".to_string());
+    let v = match send_request(&redis_client, instrument).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    instrument_response(v)
+}
+
+#[patch("/admin/instruments/{symbol}")]
+pub async fn set_instrument_status(path:Path<String>,body:Json<InstrumentStatusInput>,redis_client:Data<RedisPool>) ->impl Responder{
+
+    let mut status = serde_json::to_value(&body.0).unwrap();
+    status["type"] = Value::String("set_instrument_status".to_string());
+    status["symbol"] = Value::String(path.into_inner());
+    let v = match send_request(&redis_client, status).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    instrument_response(v)
+}
+
+#[get("/candles")]
+pub async fn get_candles(query:Query<CandleQuery>,redis_client:Data<RedisPool>) ->impl Responder{
+
+    let mut request = serde_json::to_value(&query.0).unwrap();
+    request["type"] = Value::String("candles".to_string());
+    let v = match send_request(&redis_client, request).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    if let Some(error) = v["error"].as_str() {
+        return HttpResponse::BadRequest().json(ErrorOutput{
+            success:Success::False,
+            error:error.to_string(),
+            code:v["code"].as_str().map(str::to_string)
+        });
+    }
+    HttpResponse::Ok().json(CandlesOutput{
+        success:Success::True,
+        symbol:query.0.symbol,
+        interval:query.0.interval,
+        candles:serde_json::from_value(v["candles"].clone()).unwrap_or_default()
+    })
+}
+
+#[post("/accounts/{user_id}/deposit")]
+pub async fn deposit(path:Path<u32>,body:Json<TransferInput>,redis_client:Data<RedisPool>) ->impl Responder{
+    transfer(path.into_inner(), body.0, "deposit", &redis_client).await
+}
+
+#[post("/accounts/{user_id}/withdraw")]
+pub async fn withdraw(path:Path<u32>,body:Json<TransferInput>,redis_client:Data<RedisPool>) ->impl Responder{
+    transfer(path.into_inner(), body.0, "withdraw", &redis_client).await
+}
+
+#[get("/accounts/{user_id}/balances")]
+pub async fn get_balances(path:Path<u32>,redis_client:Data<RedisPool>) ->impl Responder{
+
+    let user_id = path.into_inner();
+    let request = json!({
+        "type": "balances",
+        "user_id": user_id
+    });
+    let v = match send_request(&redis_client, request).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    HttpResponse::Ok().json(BalancesOutput{
+        success:Success::True,
+        user_id,
+        balances:serde_json::from_value(v["balances"].clone()).unwrap_or_default()
+    })
+}
+
+async fn transfer(user_id:u32, mut input:TransferInput, request_type:&str, redis_client:&RedisPool) -> HttpResponse{
+
+    input.user_id = user_id;
+    let mut request = serde_json::to_value(&input).unwrap();
+    request["type"] = Value::String(request_type.to_string());
+    let v = match send_request(redis_client, request).await {
+        Ok(v) => v,
+        Err(res) => return res
+    };
+
+    if let Some(error) = v["error"].as_str() {
+        return HttpResponse::BadRequest().json(ErrorOutput{
+            success:Success::False,
+            error:error.to_string(),
+            code:v["code"].as_str().map(str::to_string)
+        });
+    }
+    match serde_json::from_value(v) {
+        Ok(balance) => HttpResponse::Ok().json(BalanceOutput{
+            success:Success::True,
+            user_id,
+            balance
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish()
+    }
+}
+
+fn instrument_response(v:Value) -> HttpResponse{
+    if let Some(error) = v["error"].as_str() {
+        return HttpResponse::BadRequest().json(ErrorOutput{
+            success:Success::False,
+            error:error.to_string(),
+            code:v["code"].as_str().map(str::to_string)
+        });
+    }
+    match serde_json::from_value(v["instrument"].clone()) {
+        Ok(instrument) => HttpResponse::Ok().json(InstrumentOutput{
+            success:Success::True,
+            instrument
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish()
+    }
+}
+
+/// Pushes a request onto the worker queue and waits for the worker's reply
+/// on `order_response:{request_id}`.
+async fn send_request(redis_client:&RedisPool, mut request:Value) -> Result<Value, HttpResponse>{
+
     let request_id = Uuid::new_v4().to_string();
     let response_channel = format!("order_response:{}",request_id);
 
-    let mut order_with_id = serde_json::to_value(&body.0).unwrap();
-    order_with_id["request_id"] = Value::String(request_id.clone());
-    let serialized_order = order_with_id.to_string();
+    request["request_id"] = Value::String(request_id.clone());
+    let serialized_request = request.to_string();
 
     let pubsub_conn = match redis_client.get_async_connection().await {
         Ok(c) =>c,
         Err(e)=>{
             eprintln!("Error Creating Pub Sub Connection : {:?}",e);
-            return HttpResponse::InternalServerError().finish();
+            return Err(HttpResponse::InternalServerError().finish());
         }
     };
     let mut pubsub = pubsub_conn.into_pubsub();
     if let Err(e) = pubsub.subscribe(&response_channel).await {
         eprintln!("Failed to subscribe: {}", e);
-        return HttpResponse::InternalServerError().finish();
+        return Err(HttpResponse::InternalServerError().finish());
     }
 
     let mut conn = match redis_client.get_async_connection().await {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Redis connection error: {}", e);
-            return HttpResponse::InternalServerError().finish();
+            return Err(HttpResponse::InternalServerError().finish());
         }
     };
-    let res:redis::RedisResult<()> = conn.rpush("order", serialized_order).await;
+    let res:redis::RedisResult<()> = conn.rpush("order", serialized_request).await;
     if let Err(e) = res{
          eprintln!("Failed to push to Queue: {}", e);
-        return HttpResponse::InternalServerError().finish();
+        return Err(HttpResponse::InternalServerError().finish());
     };
     let msg = pubsub.on_message().next().await;
 
@@ -49,22 +269,26 @@ pub async fn create_order(body:Json<CreateOrderInput>,redis_client:Data<RedisPoo
             let payload:redis::RedisResult<String> = m.get_payload();
             match payload {
                 Ok(json)=>{
-                    let v:Value = serde_json::from_str(&json).unwrap_or_default();
-                    HttpResponse::Ok().json(CreateOrderOutput{
-                        success:Success::True,
-                        order_id:v["result_id"].as_u64().unwrap_or(0) as u32
-                    })
+                    Ok(serde_json::from_str(&json).unwrap_or_default())
                 }
                 Err(e)=>{
                     eprint!("Error Deserializing Message  :{:?}",e);
-                    HttpResponse::InternalServerError().finish()
+                    Err(HttpResponse::InternalServerError().finish())
                 }
             }
         }
-        None => HttpResponse::InternalServerError().finish()
-    }  
+        None => Err(HttpResponse::InternalServerError().finish())
+    }
 }
 pub fn init(cfg:&mut web::ServiceConfig){
     cfg.service(create_order);
+    cfg.service(cancel_order);
+    cfg.service(modify_order);
+    cfg.service(list_instruments);
+    cfg.service(add_instrument);
+    cfg.service(set_instrument_status);
+    cfg.service(get_candles);
+    cfg.service(deposit);
+    cfg.service(withdraw);
+    cfg.service(get_balances);
 }
-
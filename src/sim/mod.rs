@@ -1,2 +1,3 @@
+#[allow(clippy::module_inception)]
 pub mod sim;
 pub mod runner;
\ No newline at end of file
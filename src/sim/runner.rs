@@ -11,7 +11,7 @@ impl Default for SimulatorConfig {
     fn default() -> Self {
         Self {
             symbols: vec![
-                Symbol::BTCUSD,
+                Symbol::from("BTCUSD"),
             ],
             base_prices: vec![100000.0],
             volatilities: vec![0.0003],
@@ -2,7 +2,7 @@ use rand::{Rng, thread_rng};
 use reqwest::Client;
 use std::{collections::VecDeque, thread::sleep, time::Duration};
 
-use crate::inputs::{CreateOrderInput, OrderType, Side, Symbol};
+use crate::inputs::{CreateOrderInput, Decimal, OrderType, PostOnly, SelfTradePrevention, Side, Symbol, TimeInForce};
 pub struct OrderSimulator {
     client: Client,
     server_url: String,
@@ -44,7 +44,7 @@ impl OrderSimulator {
     }
     pub async fn start_simulation(&mut self) {
         println!(
-            "Starting order simulation for {:?} at base price ${:.2}",
+            "Starting order simulation for {} at base price ${:.2}",
             self.symbol, self.base_price
         );
         println!(
@@ -66,7 +66,7 @@ impl OrderSimulator {
                         Side::Sell => "🔴",
                     };
                     println!(
-                        "{} {} {} {} @ ${:.2} - Response: {}",
+                        "{} {} {} {} @ ${} - Response: {}",
                         side_icon,
                         chrono::Utc::now().format("%H:%M:%S"),
                         format!("{:?}", side).to_uppercase(),
@@ -140,10 +140,17 @@ impl OrderSimulator {
         let order = CreateOrderInput {
             symbol:self.symbol.clone(),
             side,
-            quantity,
-            price: (price * 100.0).round() / 100.0,
+            quantity: Decimal::from(quantity.to_string()),
+            price: Decimal::from(format!("{:.2}", price)),
             user_id: 1,
-            order_type:OrderType::Limit
+            order_type:OrderType::Limit,
+            time_in_force:TimeInForce::GTC,
+            expire_time:None,
+            stop_price:None,
+            post_only:PostOnly::Off,
+            display_quantity:None,
+            self_trade_prevention:SelfTradePrevention::Off,
+            max_spend:None
         };
 
         (order, side) 
@@ -154,7 +161,7 @@ impl OrderSimulator {
     ) -> Result<String, Box<dyn std::error::Error>> {
         let response = self
             .client
-            .post(&format!("{}/order", self.server_url))
+            .post(format!("{}/order", self.server_url))
             .json(order)
             .send()
             .await?;
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::inputs::{Accounts, OrderBook, OrderId};
+
+/// Bumped whenever the serialized layout of `Snapshot` or `OrderBook` changes.
+pub const SNAPSHOT_VERSION: u32 = 7;
+
+const MAGIC: &[u8; 8] = b"OBSNAPSH";
+const HEADER_LEN: usize = 16;
+
+/// Every book, the order id counter and the accounts as of a point in the journal.
+/// File layout: `[magic: 8 bytes][version: u32 LE][crc32 of payload: u32 LE][payload: JSON Snapshot]`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Unix seconds
+    pub taken_at: i64,
+    /// Journal offset of the first command not reflected in the books
+    pub journal_offset: u64,
+    pub next_order_id: OrderId,
+    pub books: Vec<OrderBook>,
+    pub accounts: Accounts,
+}
+
+impl Snapshot {
+    /// Writes to a temporary file and renames it over `path`, so a crash
+    /// leaves the previous snapshot intact.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let payload = serde_json::to_vec(self)?;
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        file.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// The snapshot at `path`, or `None` if there is none yet. A damaged file
+    /// or one from another version is an error rather than an empty start.
+    pub fn read(path: impl AsRef<Path>) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        match File::open(path.as_ref()) {
+            Ok(mut file) => {
+                file.read_to_end(&mut bytes)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let header = bytes.get(..HEADER_LEN).ok_or("Snapshot is truncated")?;
+        if &header[..8] != MAGIC {
+            return Err("Not a snapshot file".into());
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into()?);
+        if version != SNAPSHOT_VERSION {
+            return Err(format!("Snapshot version {} is not supported (expected {})", version, SNAPSHOT_VERSION).into());
+        }
+        let checksum = u32::from_le_bytes(header[12..16].try_into()?);
+        let payload = &bytes[HEADER_LEN..];
+        if crc32fast::hash(payload) != checksum {
+            return Err("Snapshot checksum mismatch".into());
+        }
+        Ok(Some(serde_json::from_slice(payload)?))
+    }
+
+    /// Problems found in the books, each prefixed with its symbol; empty for
+    /// a snapshot that is safe to recover from.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut symbols = HashSet::new();
+        let mut order_ids = HashSet::new();
+
+        for book in &self.books {
+            let symbol = &book.instrument.symbol;
+            if !symbols.insert(symbol) {
+                problems.push(format!("{}: duplicate book", symbol));
+            }
+            problems.extend(book.validate().into_iter().map(|p| format!("{}: {}", symbol, p)));
+            for &order_id in book.order_index.keys() {
+                if order_id >= self.next_order_id {
+                    problems.push(format!("{}: order {} is not below next_order_id {}", symbol, order_id, self.next_order_id));
+                }
+                if !order_ids.insert(order_id) {
+                    problems.push(format!("{}: order {} is also in another book", symbol, order_id));
+                }
+            }
+        }
+
+        problems.extend(self.accounts.validate().into_iter().map(|p| format!("accounts: {}", p)));
+        for order_id in self.accounts.order_locks.keys() {
+            if !order_ids.contains(order_id) {
+                problems.push(format!("accounts: funds reserved for order {} which is not in any book", order_id));
+            }
+        }
+        problems
+    }
+}
@@ -0,0 +1,197 @@
+use std::{fmt, iter::Sum, ops::{Add, AddAssign, Sub, SubAssign}};
+
+use serde::{Deserialize, Serialize};
+
+/// Decimal amount exactly as the client sent it, either `"0.29"` or `0.29`.
+/// It is kept as text so it never goes through floating point before being
+/// scaled to an instrument's integer units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "DecimalRepr", into = "String")]
+pub struct Decimal(String);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DecimalRepr {
+    Text(String),
+    Number(serde_json::Number),
+}
+
+impl From<DecimalRepr> for Decimal {
+    fn from(repr: DecimalRepr) -> Self {
+        match repr {
+            DecimalRepr::Text(text) => Decimal(text),
+            DecimalRepr::Number(number) => Decimal(number.to_string()),
+        }
+    }
+}
+
+impl From<Decimal> for String {
+    fn from(decimal: Decimal) -> Self {
+        decimal.0
+    }
+}
+
+impl From<String> for Decimal {
+    fn from(text: String) -> Self {
+        Decimal(text)
+    }
+}
+
+impl From<&str> for Decimal {
+    fn from(text: &str) -> Self {
+        Decimal(text.to_string())
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Decimal {
+    /// Digits after the decimal point as written, e.g. 2 for `"0.01"`.
+    pub fn scale(&self) -> u32 {
+        self.0.trim().split_once('.').map_or(0, |(_, fraction)| fraction.len() as u32)
+    }
+
+    /// Scales the value to a whole number of `10^-decimals` units. Returns `None`
+    /// for negative or malformed input, for non-zero digits beyond `decimals`
+    /// places, and on overflow.
+    pub fn to_units(&self, decimals: u32) -> Option<u64> {
+        let text = self.0.trim();
+        let text = text.strip_prefix('+').unwrap_or(text);
+        let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let (kept, dropped) = fraction.split_at(fraction.len().min(decimals as usize));
+        if dropped.chars().any(|c| c != '0') {
+            return None;
+        }
+
+        let scale = 10u64.checked_pow(decimals)?;
+        let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+        let kept_units: u64 = if kept.is_empty() { 0 } else { kept.parse().ok()? };
+        let kept_scale = 10u64.checked_pow(decimals - kept.len() as u32)?;
+
+        whole.checked_mul(scale)?.checked_add(kept_units.checked_mul(kept_scale)?)
+    }
+}
+
+/// Renders `units` of `10^-decimals` as a decimal string, e.g. `(29, 2)` -> `"0.29"`.
+pub fn format_units(units: u128, decimals: u32) -> String {
+    if decimals == 0 {
+        return units.to_string();
+    }
+    let scale = 10u128.pow(decimals);
+    format!("{}.{:0width$}", units / scale, units % scale, width = decimals as usize)
+}
+
+/// Price as a whole number of the instrument's smallest price unit
+/// (`10^-price_decimals`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Price(pub u64);
+
+/// Quantity as a whole number of the instrument's smallest quantity unit
+/// (`10^-quantity_decimals`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Quantity(pub u64);
+
+/// Decimals every asset balance is held in, whichever instrument it trades on.
+/// An instrument's `price_decimals + quantity_decimals` may not exceed it, so
+/// every notional converts exactly.
+pub const ASSET_DECIMALS: u32 = 8;
+
+/// Funds of one asset as a whole number of `10^-ASSET_DECIMALS` units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Amount(pub u64);
+
+macro_rules! unit_ops {
+    ($unit:ident) => {
+        impl Add for $unit {
+            type Output = $unit;
+            fn add(self, rhs: $unit) -> $unit {
+                $unit(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $unit {
+            type Output = $unit;
+            fn sub(self, rhs: $unit) -> $unit {
+                $unit(self.0 - rhs.0)
+            }
+        }
+
+        impl AddAssign for $unit {
+            fn add_assign(&mut self, rhs: $unit) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl SubAssign for $unit {
+            fn sub_assign(&mut self, rhs: $unit) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl Sum for $unit {
+            fn sum<I: Iterator<Item = $unit>>(iter: I) -> $unit {
+                $unit(iter.map(|u| u.0).sum())
+            }
+        }
+
+        impl $unit {
+            pub const ZERO: $unit = $unit(0);
+
+            pub fn is_zero(self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn saturating_add(self, rhs: $unit) -> $unit {
+                $unit(self.0.saturating_add(rhs.0))
+            }
+
+            pub fn saturating_sub(self, rhs: $unit) -> $unit {
+                $unit(self.0.saturating_sub(rhs.0))
+            }
+
+            pub fn checked_add(self, rhs: $unit) -> Option<$unit> {
+                self.0.checked_add(rhs.0).map($unit)
+            }
+
+            pub fn checked_sub(self, rhs: $unit) -> Option<$unit> {
+                self.0.checked_sub(rhs.0).map($unit)
+            }
+        }
+    };
+}
+
+unit_ops!(Price);
+unit_ops!(Quantity);
+unit_ops!(Amount);
+
+/// Serializes a `Price -> Quantity` map as `[price, quantity]` pairs. JSON object
+/// keys are strings, which don't read back as integers inside tagged enums.
+pub mod level_pairs {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{Price, Quantity};
+
+    pub fn serialize<S: Serializer>(levels: &BTreeMap<Price, Quantity>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(levels.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<Price, Quantity>, D::Error> {
+        Ok(Vec::<(Price, Quantity)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}